@@ -2,18 +2,29 @@ use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use chrono::{TimeZone, Utc};
+use itertools::Itertools;
 use twilight_interactions::command::{CommandModel, CreateCommand};
 use twilight_model::{
     application::interaction::application_command::CommandData,
+    channel::message::{Embed, MessageFlags},
     gateway::payload::incoming::InteractionCreate,
+    guild::Permissions,
     http::interaction::{InteractionResponse, InteractionResponseType},
+    id::{
+        marker::{RoleMarker, UserMarker},
+        Id,
+    },
 };
 use twilight_util::{
     builder::{embed::EmbedBuilder, InteractionResponseDataBuilder},
     snowflake::Snowflake,
 };
 
-use crate::models::ChairContext;
+use crate::{
+    discord::MessageEdit,
+    models::{ChairContext, LFGType, MultiRoleMode},
+    util::{embed_response, simple_embed},
+};
 
 #[derive(CommandModel, CreateCommand)]
 #[command(name = "ping", desc = "Check the latency of the bot")]
@@ -47,7 +58,8 @@ impl PingCommand {
             .color(0x85db5e)
             .title("Ping")
             .description(format!("`•` The time it takes for the bot to talk to Discord is `{}` (exact)
-                            `•` The time it took for this command to execute roundtrip is `{}` (approximation)", display_latency, display_roundtrip))
+                            `•` The time it took for this command to execute roundtrip is `{}` (approximation)
+                            `•` This command was served by shard `{}`", display_latency, display_roundtrip, context.shard))
                             .build();
 
         let client = context.http.interaction(interaction.application_id);
@@ -68,8 +80,20 @@ impl PingCommand {
     }
 }
 
+/// Members with these permissions can manage LFG types without being a bot administrator
+pub const LFG_DATA_PERMISSIONS: Permissions = Permissions::MANAGE_ROLES;
+
+fn lfg_data_permissions() -> Permissions {
+    LFG_DATA_PERMISSIONS
+}
+
 #[derive(CommandModel, CreateCommand)]
-#[command(name = "lfgdata", desc = "Manage LFG types [KuNet only]")]
+#[command(
+    name = "lfgdata",
+    desc = "Manage LFG types",
+    default_permissions = "lfg_data_permissions",
+    dm_permission = false
+)]
 pub enum LFGDataCommand {
     #[command(name = "list")]
     List(LFGList),
@@ -77,6 +101,8 @@ pub enum LFGDataCommand {
     Add(LFGAdd),
     #[command(name = "remove")]
     Remove(LFGRemove),
+    #[command(name = "multirole")]
+    MultiRole(LFGMultiRole),
 }
 
 impl LFGDataCommand {
@@ -92,10 +118,104 @@ impl LFGDataCommand {
             LFGDataCommand::List(command) => command.run(interaction, context).await,
             LFGDataCommand::Add(command) => command.run(interaction, context).await,
             LFGDataCommand::Remove(command) => command.run(interaction, context).await,
+            LFGDataCommand::MultiRole(command) => command.run(interaction, context).await,
         }
     }
 }
 
+pub async fn respond(
+    interaction: &InteractionCreate,
+    context: &ChairContext,
+    embed: Embed,
+    ephemeral: bool,
+) -> Result<()> {
+    context
+        .discord
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            embed_response(embed, ephemeral),
+        )
+        .await
+        .context("responding to command")?;
+
+    Ok(())
+}
+
+/// Acknowledges the command straight away, for commands that wait on something slow.
+/// Answer it with [`respond_deferred`]
+pub async fn defer(
+    interaction: &InteractionCreate,
+    context: &ChairContext,
+    ephemeral: bool,
+) -> Result<()> {
+    let mut data = InteractionResponseDataBuilder::new();
+    if ephemeral {
+        data = data.flags(MessageFlags::EPHEMERAL);
+    }
+
+    let response = InteractionResponse {
+        kind: InteractionResponseType::DeferredChannelMessageWithSource,
+        data: Some(data.build()),
+    };
+
+    context
+        .discord
+        .create_response(interaction.id, &interaction.token, response)
+        .await
+        .context("deferring command")?;
+
+    Ok(())
+}
+
+pub async fn respond_deferred(
+    interaction: &InteractionCreate,
+    context: &ChairContext,
+    embed: Embed,
+) -> Result<()> {
+    let edit = MessageEdit {
+        embeds: Some(vec![embed]),
+        ..Default::default()
+    };
+    context
+        .discord
+        .update_response(&interaction.token, edit)
+        .await
+        .context("responding to deferred command")?;
+
+    Ok(())
+}
+
+pub fn parse_role_id(value: &str) -> Option<Id<RoleMarker>> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("<@&")
+        .and_then(|it| it.strip_suffix('>'))
+        .unwrap_or(value);
+
+    value.parse().ok()
+}
+
+/// Reads colours written like `#8ae24a`, `0x8ae24a` or `8ae24a`
+fn parse_color(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('#')
+        .or_else(|| value.strip_prefix("0x"))
+        .unwrap_or(value);
+
+    u32::from_str_radix(value, 16)
+        .ok()
+        .filter(|it| *it <= 0xffffff)
+}
+
+fn team_label(lfg_type: &LFGType) -> String {
+    match lfg_type.team_size {
+        Some(v) => format!(" (teams of `{v}`)"),
+        None => String::new(),
+    }
+}
+
 #[derive(CommandModel, CreateCommand)]
 #[command(name = "list", desc = "List the LFG types")]
 pub struct LFGList;
@@ -106,17 +226,66 @@ impl LFGList {
         interaction: InteractionCreate,
         context: Arc<ChairContext>,
     ) -> Result<()> {
-        Ok(())
+        let guild_id = match interaction.guild_id {
+            Some(v) => v,
+            None => {
+                let embed = simple_embed(0xff3030, "Guild only", "Use this in a server")?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+
+        let lfg_types = context.storage.mention_types(guild_id)?;
+
+        let description = if lfg_types.is_empty() {
+            "There are no LFG types yet, add one with `/lfgdata add`".to_owned()
+        } else {
+            lfg_types
+                .iter()
+                .map(|it| {
+                    format!(
+                        "`•` <@&{}> → <@&{}>{}",
+                        it.facade,
+                        it.actual,
+                        team_label(it)
+                    )
+                })
+                .join("\n")
+        };
+
+        let embed = simple_embed(0x85db5e, "LFG types", &description)?;
+        respond(&interaction, &context, embed, false).await
     }
 }
 
 #[derive(CommandModel, CreateCommand)]
-#[command(name = "add", desc = "Add to the LFG types")]
+#[command(name = "add", desc = "Add to the LFG types, or change one")]
 pub struct LFGAdd {
-    /// The facade role id
-    pub facade: String,
-    /// The actual role id
-    pub actual: String,
+    /// The role people ping
+    pub facade: Id<RoleMarker>,
+    /// The role that actually gets pinged
+    pub actual: Id<RoleMarker>,
+    /// Players in a full team, lets people ping with `+1` or no count at all
+    #[command(min_value = 2, max_value = 99)]
+    pub team_size: Option<i64>,
+    /// Minutes before an unfilled ping expires
+    #[command(min_value = 1, max_value = 1440)]
+    pub expiry_minutes: Option<i64>,
+    /// The most players a ping can ask for
+    #[command(min_value = 2, max_value = 99)]
+    pub max_players: Option<i64>,
+    /// Colour of live pings, like #8ae24a
+    pub color: Option<String>,
+    /// Colour of expired pings, like #ff3030
+    pub expired_color: Option<String>,
+    /// Label of the join button
+    #[command(max_length = 80)]
+    pub button_label: Option<String>,
+    /// Shown once everyone is ready
+    #[command(max_length = 1000)]
+    pub completed_message: Option<String>,
+    /// Shown when a ping expires, replacing the current ones
+    #[command(max_length = 1000)]
+    pub expired_message: Option<String>,
 }
 
 impl LFGAdd {
@@ -125,14 +294,123 @@ impl LFGAdd {
         interaction: InteractionCreate,
         context: Arc<ChairContext>,
     ) -> Result<()> {
-        Ok(())
+        let guild_id = match interaction.guild_id {
+            Some(v) => v,
+            None => {
+                let embed = simple_embed(0xff3030, "Guild only", "Use this in a server")?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+
+        let (facade, actual) = (self.facade, self.actual);
+
+        if facade == actual {
+            let embed = simple_embed(
+                0xff3030,
+                "Invalid roles",
+                "The facade and the actual role have to be different roles",
+            )?;
+            return respond(&interaction, &context, embed, true).await;
+        }
+
+        // an existing type keeps whatever settings weren't given
+        let existing = context.storage.mention_type(guild_id, facade)?;
+        let updated = existing.is_some();
+        let mut lfg_type = match existing {
+            Some(v) => v,
+            None => {
+                let mut lfg_type = LFGType::new(facade, actual);
+                lfg_type.expiry_minutes = context.config.default_expiry_minutes;
+                lfg_type
+            }
+        };
+        lfg_type.actual = actual;
+
+        if let Some(v) = self.team_size {
+            lfg_type.team_size = Some(v as u8);
+        }
+        if let Some(v) = self.expiry_minutes {
+            lfg_type.expiry_minutes = v as u32;
+        }
+        if let Some(v) = self.max_players {
+            lfg_type.max_denominator = v as u8;
+        }
+        if let Some(v) = &self.button_label {
+            lfg_type.button_label = v.clone();
+        }
+        if let Some(v) = &self.completed_message {
+            lfg_type.completed_message = v.clone();
+        }
+        if let Some(v) = &self.expired_message {
+            lfg_type.expired_messages = vec![v.clone()];
+        }
+
+        for (value, target) in [
+            (&self.color, &mut lfg_type.color),
+            (&self.expired_color, &mut lfg_type.expired_color),
+        ] {
+            let value = match value {
+                Some(v) => v,
+                None => continue,
+            };
+
+            match parse_color(value) {
+                Some(v) => *target = v,
+                None => {
+                    let embed = simple_embed(
+                        0xff3030,
+                        "Invalid colour",
+                        &format!("`{value}` is not a colour, use something like `#8ae24a`"),
+                    )?;
+                    return respond(&interaction, &context, embed, true).await;
+                }
+            }
+        }
+
+        if let Some(team_size) = lfg_type.team_size {
+            if team_size > lfg_type.max_denominator {
+                let embed = simple_embed(
+                    0xff3030,
+                    "Invalid team size",
+                    &format!(
+                        "Teams can have at most `{}` players",
+                        lfg_type.max_denominator
+                    ),
+                )?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        }
+
+        context.storage.store_mention_type(guild_id, &lfg_type)?;
+
+        let embed = if updated {
+            simple_embed(
+                0x85db5e,
+                "Updated LFG type",
+                &format!(
+                    "<@&{facade}> was already an LFG type, it now pings <@&{actual}>{}. Settings you left out were kept",
+                    team_label(&lfg_type)
+                ),
+            )?
+        } else {
+            simple_embed(
+                0x85db5e,
+                "Added LFG type",
+                &format!(
+                    "<@&{facade}> now pings <@&{actual}>{}",
+                    team_label(&lfg_type)
+                ),
+            )?
+        };
+        respond(&interaction, &context, embed, false).await
     }
 }
 
 #[derive(CommandModel, CreateCommand)]
 #[command(name = "remove", desc = "Remove from the LFG types")]
 pub struct LFGRemove {
-    /// The facade role id
+    /// The role people ping
+    #[command(autocomplete = true)]
     pub facade: String,
 }
 
@@ -142,6 +420,149 @@ impl LFGRemove {
         interaction: InteractionCreate,
         context: Arc<ChairContext>,
     ) -> Result<()> {
-        Ok(())
+        let guild_id = match interaction.guild_id {
+            Some(v) => v,
+            None => {
+                let embed = simple_embed(0xff3030, "Guild only", "Use this in a server")?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+
+        let facade = match parse_role_id(&self.facade) {
+            Some(v) => v,
+            None => {
+                let embed = simple_embed(
+                    0xff3030,
+                    "Invalid role",
+                    "Pick one of the suggested LFG roles",
+                )?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+
+        if !context.storage.remove_mention_type(guild_id, facade)? {
+            let embed = simple_embed(
+                0xff3030,
+                "Unknown LFG type",
+                &format!("<@&{facade}> is not an LFG type"),
+            )?;
+            return respond(&interaction, &context, embed, true).await;
+        }
+
+        let embed = simple_embed(
+            0x85db5e,
+            "Removed LFG type",
+            &format!("<@&{facade}> is no longer an LFG type"),
+        )?;
+        respond(&interaction, &context, embed, false).await
+    }
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "multirole",
+    desc = "Choose what happens when a message pings more than one LFG role"
+)]
+pub struct LFGMultiRole {
+    /// How to handle the roles
+    pub mode: MultiRoleMode,
+}
+
+impl LFGMultiRole {
+    pub async fn run(
+        &self,
+        interaction: InteractionCreate,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let guild_id = match interaction.guild_id {
+            Some(v) => v,
+            None => {
+                let embed = simple_embed(0xff3030, "Guild only", "Use this in a server")?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+
+        let mut settings = context
+            .storage
+            .guild_settings(guild_id)?
+            .unwrap_or_default();
+        settings.multi_role_mode = self.mode;
+        context.storage.store_guild_settings(guild_id, &settings)?;
+
+        let description = match self.mode {
+            MultiRoleMode::Combined => {
+                "Pinging several LFG roles makes one ping that tags them all"
+            }
+            MultiRoleMode::Separate => {
+                "Pinging several LFG roles makes a ping for each, joining one joins them all"
+            }
+        };
+
+        let embed = simple_embed(0x85db5e, "Updated LFG settings", description)?;
+        respond(&interaction, &context, embed, false).await
+    }
+}
+
+fn administrator_permissions() -> Permissions {
+    Permissions::ADMINISTRATOR
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "administrator",
+    desc = "Manage the bot administrators [owners only]",
+    default_permissions = "administrator_permissions"
+)]
+pub enum AdministratorCommand {
+    #[command(name = "grant")]
+    Grant(AdministratorGrant),
+    #[command(name = "revoke")]
+    Revoke(AdministratorRevoke),
+}
+
+impl AdministratorCommand {
+    pub async fn handle(
+        interaction: InteractionCreate,
+        data: CommandData,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let command =
+            AdministratorCommand::from_interaction(data.into()).context("parsing command data")?;
+
+        let (user, administrator) = match command {
+            AdministratorCommand::Grant(command) => (command.user, true),
+            AdministratorCommand::Revoke(command) => (command.user, false),
+        };
+
+        context.users.set_administrator(user, administrator)?;
+
+        let embed = if administrator {
+            simple_embed(
+                0x85db5e,
+                "Granted administrator",
+                &format!("<@{user}> is now a bot administrator"),
+            )?
+        } else {
+            simple_embed(
+                0x85db5e,
+                "Revoked administrator",
+                &format!("<@{user}> is no longer a bot administrator"),
+            )?
+        };
+        respond(&interaction, &context, embed, true).await
     }
 }
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "grant", desc = "Make a user a bot administrator")]
+pub struct AdministratorGrant {
+    /// The user to grant administrator to
+    pub user: Id<UserMarker>,
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "revoke", desc = "Take bot administrator away from a user")]
+pub struct AdministratorRevoke {
+    /// The user to revoke administrator from
+    pub user: Id<UserMarker>,
+}
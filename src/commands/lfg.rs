@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use twilight_interactions::command::{CommandModel, CreateCommand};
+use twilight_model::{
+    application::{
+        command::{CommandOptionChoice, CommandOptionChoiceValue},
+        interaction::application_command::CommandData,
+    },
+    channel::message::AllowedMentions,
+    gateway::payload::incoming::InteractionCreate,
+    http::interaction::{InteractionResponse, InteractionResponseType},
+    id::{
+        marker::{GuildMarker, UserMarker},
+        Id,
+    },
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::{lfg::LFGRequest, models::ChairContext, parser::Count, util::simple_embed};
+
+use super::admin::{parse_role_id, respond};
+
+/// Suggests the guild's LFG roles by name, matching what has been typed so far
+pub fn lfg_role_choices(
+    context: &ChairContext,
+    guild: Id<GuildMarker>,
+    partial: &str,
+) -> Result<Vec<CommandOptionChoice>> {
+    let partial = partial.trim().to_lowercase();
+
+    let choices = context
+        .storage
+        .mention_types(guild)?
+        .into_iter()
+        .map(|it| {
+            let name = match context.cache.role(it.facade) {
+                Some(role) => format!("@{}", role.resource().name),
+                None => it.facade.to_string(),
+            };
+            (it.facade, name)
+        })
+        .filter(|(id, name)| {
+            name.to_lowercase().contains(&partial) || id.to_string().starts_with(&partial)
+        })
+        .take(25)
+        .map(|(id, name)| CommandOptionChoice {
+            name,
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(id.to_string()),
+        })
+        .collect();
+
+    Ok(choices)
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "lfg", desc = "Ping an LFG role", dm_permission = false)]
+pub struct LFGCommand {
+    /// The LFG role to ping
+    #[command(autocomplete = true)]
+    pub role: String,
+    /// How many are playing already, including you
+    #[command(min_value = 1, max_value = 99)]
+    pub have: i64,
+    /// How many are needed in total
+    #[command(min_value = 2, max_value = 99)]
+    pub need: i64,
+    /// Anything the others should know
+    #[command(max_length = 200)]
+    pub note: Option<String>,
+    /// Someone already playing with you
+    pub teammate1: Option<Id<UserMarker>>,
+    /// Someone already playing with you
+    pub teammate2: Option<Id<UserMarker>>,
+    /// Someone already playing with you
+    pub teammate3: Option<Id<UserMarker>>,
+}
+
+impl LFGCommand {
+    pub async fn handle(
+        interaction: InteractionCreate,
+        data: CommandData,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let command = LFGCommand::from_interaction(data.into()).context("parsing command data")?;
+        command.run(interaction, context).await
+    }
+
+    async fn run(&self, interaction: InteractionCreate, context: Arc<ChairContext>) -> Result<()> {
+        let (guild_id, channel_id) = match (interaction.guild_id, &interaction.channel) {
+            (Some(guild), Some(channel)) => (guild, channel.id),
+            _ => {
+                let embed = simple_embed(0xff3030, "Guild only", "Use this in a server")?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+        let author = interaction.author_id().context("lfg has no author")?;
+
+        let lfg_type = match parse_role_id(&self.role)
+            .map(|it| context.storage.mention_type(guild_id, it))
+            .transpose()?
+            .flatten()
+        {
+            Some(v) => v,
+            None => {
+                let embed = simple_embed(
+                    0xff3030,
+                    "Not an LFG role",
+                    "Pick one of the suggested LFG roles",
+                )?;
+                return respond(&interaction, &context, embed, true).await;
+            }
+        };
+
+        if self.need > lfg_type.max_denominator as i64 {
+            let embed = simple_embed(
+                lfg_type.expired_color,
+                "Too many players",
+                &format!(
+                    "This LFG role only goes up to `{}` players",
+                    lfg_type.max_denominator
+                ),
+            )?;
+            return respond(&interaction, &context, embed, true).await;
+        }
+
+        let teammates = [self.teammate1, self.teammate2, self.teammate3]
+            .into_iter()
+            .flatten()
+            .filter(|it| *it != author)
+            .unique()
+            .collect::<Vec<_>>();
+
+        if (teammates.len() as i64 + 1).max(self.have) >= self.need {
+            let embed = simple_embed(
+                lfg_type.expired_color,
+                "Already full",
+                "You already have everyone you need!",
+            )?;
+            return respond(&interaction, &context, embed, true).await;
+        }
+
+        let mut content = format!("<@{author}> is looking for a game!");
+        if let Some(note) = &self.note {
+            content += &format!("\n> {}", note.replace('\n', " "));
+        }
+
+        let data = InteractionResponseDataBuilder::new()
+            .content(content)
+            .allowed_mentions(AllowedMentions::default())
+            .build();
+        let response = InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(data),
+        };
+
+        context
+            .discord
+            .create_response(interaction.id, &interaction.token, response)
+            .await
+            .context("responding to lfg")?;
+
+        let message = context
+            .discord
+            .response_message(&interaction.token)
+            .await
+            .context("fetching lfg response")?;
+
+        let request = LFGRequest {
+            guild: guild_id,
+            channel: channel_id,
+            original_message: message,
+            author,
+            count: Count::Fraction {
+                have: self.have as u8,
+                need: self.need as u8,
+            },
+            mentions: teammates,
+            extra_tags: Vec::new(),
+            cancel_button: true,
+        };
+
+        context
+            .lfg
+            .start_session(context.clone(), request, &lfg_type)
+            .await?;
+
+        Ok(())
+    }
+}
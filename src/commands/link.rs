@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use tracing::warn;
+use twilight_interactions::command::{CommandModel, CreateCommand};
+use twilight_model::{
+    application::interaction::application_command::CommandData,
+    gateway::payload::incoming::InteractionCreate,
+};
+use uuid::Uuid;
+
+use crate::{models::ChairContext, users::LinkOutcome, util::simple_embed};
+
+use super::admin::{defer, respond, respond_deferred};
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "link", desc = "Link your game accounts")]
+pub enum LinkCommand {
+    #[command(name = "add")]
+    Add(LinkAdd),
+    #[command(name = "list")]
+    List(LinkList),
+    #[command(name = "main")]
+    Main(LinkMain),
+    #[command(name = "remove")]
+    Remove(LinkRemove),
+}
+
+impl LinkCommand {
+    pub async fn handle(
+        interaction: InteractionCreate,
+        data: CommandData,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let command = LinkCommand::from_interaction(data.into()).context("parsing command data")?;
+
+        match command {
+            LinkCommand::Add(command) => command.run(interaction, context).await,
+            LinkCommand::List(command) => command.run(interaction, context).await,
+            LinkCommand::Main(command) => command.run(interaction, context).await,
+            LinkCommand::Remove(command) => command.run(interaction, context).await,
+        }
+    }
+}
+
+async fn parse_uuid(
+    interaction: &InteractionCreate,
+    context: &ChairContext,
+    value: &str,
+) -> Result<Option<Uuid>> {
+    match Uuid::parse_str(value.trim()) {
+        Ok(v) => Ok(Some(v)),
+        Err(_) => {
+            let embed = simple_embed(
+                0xff3030,
+                "Invalid UUID",
+                &format!("`{}` is not an account UUID", value.trim()),
+            )?;
+            respond(interaction, context, embed, true).await?;
+            Ok(None)
+        }
+    }
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "add", desc = "Link a game account to yourself")]
+pub struct LinkAdd {
+    /// The username or UUID of the account
+    pub account: String,
+}
+
+impl LinkAdd {
+    pub async fn run(
+        &self,
+        interaction: InteractionCreate,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let user = interaction.author_id().context("link has no author")?;
+
+        // looking the account up can take longer than discord waits for a response
+        defer(&interaction, &context, true).await?;
+
+        let account = self.account.trim();
+        let profile = match Uuid::parse_str(account) {
+            Ok(uuid) => context.profiles.by_uuid(uuid).await,
+            Err(_) => context.profiles.by_username(account).await,
+        };
+
+        let profile = match profile {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                let embed = simple_embed(
+                    0xff3030,
+                    "Unknown account",
+                    &format!("There is no account called `{account}`"),
+                )?;
+                return respond_deferred(&interaction, &context, embed).await;
+            }
+            Err(cause) => {
+                warn!(?cause, account, "error resolving account");
+                let embed = simple_embed(
+                    0xff3030,
+                    "Lookup failed",
+                    "Accounts can't be looked up right now, try again in a bit",
+                )?;
+                return respond_deferred(&interaction, &context, embed).await;
+            }
+        };
+
+        let username = &profile.name;
+
+        let embed = match context.users.link_account(user, profile.uuid, username)? {
+            LinkOutcome::Linked => simple_embed(
+                0x85db5e,
+                "Linked account",
+                &format!("`{username}` is now linked to you"),
+            )?,
+            LinkOutcome::Updated => simple_embed(
+                0x85db5e,
+                "Updated account",
+                &format!("`{username}` was already linked to you, its username has been updated"),
+            )?,
+            LinkOutcome::OwnedByOther => simple_embed(
+                0xff3030,
+                "Already linked",
+                "That account is linked to somebody else",
+            )?,
+        };
+
+        respond_deferred(&interaction, &context, embed).await
+    }
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "list", desc = "List your linked game accounts")]
+pub struct LinkList;
+
+impl LinkList {
+    pub async fn run(
+        &self,
+        interaction: InteractionCreate,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let user = interaction.author_id().context("link has no author")?;
+
+        let main_link = context.users.user(user)?.and_then(|it| it.main_link);
+        let links = context.users.linked_accounts(user)?;
+
+        let description = if links.is_empty() {
+            "You have no linked accounts, link one with `/link add`".to_owned()
+        } else {
+            links
+                .iter()
+                .map(|it| {
+                    let main = if Some(it.uuid) == main_link {
+                        " **(main)**"
+                    } else {
+                        ""
+                    };
+                    format!("`•` `{}` - `{}`{}", it.last_username, it.uuid, main)
+                })
+                .join("\n")
+        };
+
+        let embed = simple_embed(0x85db5e, "Linked accounts", &description)?;
+        respond(&interaction, &context, embed, true).await
+    }
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "main", desc = "Set your main game account")]
+pub struct LinkMain {
+    /// The UUID of the account
+    pub uuid: String,
+}
+
+impl LinkMain {
+    pub async fn run(
+        &self,
+        interaction: InteractionCreate,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let user = interaction.author_id().context("link has no author")?;
+
+        let uuid = match parse_uuid(&interaction, &context, &self.uuid).await? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let embed = if context.users.set_main_link(user, uuid)? {
+            simple_embed(
+                0x85db5e,
+                "Main account set",
+                &format!("`{uuid}` is now your main account"),
+            )?
+        } else {
+            simple_embed(0xff3030, "Not linked", "That account isn't linked to you")?
+        };
+
+        respond(&interaction, &context, embed, true).await
+    }
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "remove", desc = "Unlink a game account from yourself")]
+pub struct LinkRemove {
+    /// The UUID of the account
+    pub uuid: String,
+}
+
+impl LinkRemove {
+    pub async fn run(
+        &self,
+        interaction: InteractionCreate,
+        context: Arc<ChairContext>,
+    ) -> Result<()> {
+        let user = interaction.author_id().context("link has no author")?;
+
+        let uuid = match parse_uuid(&interaction, &context, &self.uuid).await? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let embed = if context.users.unlink_account(user, uuid)? {
+            simple_embed(
+                0x85db5e,
+                "Unlinked account",
+                &format!("`{uuid}` is no longer linked to you"),
+            )?
+        } else {
+            simple_embed(0xff3030, "Not linked", "That account isn't linked to you")?
+        };
+
+        respond(&interaction, &context, embed, true).await
+    }
+}
@@ -1,2 +1,4 @@
 pub mod admin;
+pub mod lfg;
+pub mod link;
 pub mod processor;
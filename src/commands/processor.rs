@@ -1,15 +1,38 @@
 use std::{mem, sync::Arc};
 
 use anyhow::{bail, Context, Result};
-use tracing::{info, warn};
+use itertools::Itertools;
+use tracing::{debug, info, warn};
+use twilight_http::client::InteractionClient;
 use twilight_interactions::command::CreateCommand;
 use twilight_model::{
-    application::interaction::{application_command::CommandData, InteractionData},
+    application::{
+        command::{Command, CommandOption},
+        interaction::{
+            application_command::{CommandData, CommandDataOption, CommandOptionValue},
+            message_component::MessageComponentInteractionData,
+            InteractionData, InteractionType,
+        },
+    },
     gateway::payload::incoming::InteractionCreate,
+    guild::Permissions,
+    http::interaction::{InteractionResponse, InteractionResponseType},
+    id::{marker::GuildMarker, Id},
     oauth::Application,
 };
+use twilight_util::builder::InteractionResponseDataBuilder;
+use uuid::Uuid;
 
-use crate::{commands::admin::LFGDataCommand, models::ChairContext};
+use crate::{
+    commands::{
+        admin::{AdministratorCommand, LFGDataCommand, LFG_DATA_PERMISSIONS},
+        lfg::{lfg_role_choices, LFGCommand},
+        link::LinkCommand,
+    },
+    config::{Features, RegistrationMode},
+    models::ChairContext,
+    util::{embed_response, simple_embed},
+};
 
 use super::admin::PingCommand;
 
@@ -17,14 +40,91 @@ pub async fn command_handle_interaction(
     mut interaction: Box<InteractionCreate>,
     context: Arc<ChairContext>,
 ) {
-    let data = match mem::take(&mut interaction.data) {
-        Some(InteractionData::ApplicationCommand(data)) => *data,
-        _ => return,
+    match mem::take(&mut interaction.data) {
+        Some(InteractionData::ApplicationCommand(data))
+            if interaction.kind == InteractionType::ApplicationCommandAutocomplete =>
+        {
+            if let Err(cause) = handle_autocomplete(*interaction, *data, context).await {
+                warn!(?cause, "failed to autocomplete command");
+            }
+        }
+        Some(InteractionData::ApplicationCommand(data)) => {
+            if let Err(cause) = handle_command(*interaction, *data, context).await {
+                warn!(?cause, "failed to execute command");
+            }
+        }
+        Some(InteractionData::MessageComponent(data)) => {
+            if let Err(cause) = handle_component(*interaction, data, context).await {
+                warn!(?cause, "failed to handle component");
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Who is allowed to run a command
+enum CommandAccess {
+    Everyone,
+    /// guild members with all of these permissions, bot administrators and owners
+    Permissions(Permissions),
+    /// only the owners configured in [`ChairConfig`](crate::config::ChairConfig)
+    Owner,
+}
+
+fn command_access(name: &str) -> CommandAccess {
+    match name {
+        "lfgdata" => CommandAccess::Permissions(LFG_DATA_PERMISSIONS),
+        "administrator" => CommandAccess::Owner,
+        _ => CommandAccess::Everyone,
+    }
+}
+
+fn member_permissions(interaction: &InteractionCreate, context: &ChairContext) -> Permissions {
+    let (user, guild) = match (interaction.author_id(), interaction.guild_id) {
+        (Some(user), Some(guild)) => (user, guild),
+        _ => return Permissions::empty(),
+    };
+
+    match context.cache.permissions().root(user, guild) {
+        Ok(v) => v,
+        Err(cause) => {
+            // the cache may not have seen the guild yet, discord sends what it calculated
+            debug!(?cause, "falling back to interaction permissions");
+            interaction
+                .member
+                .as_ref()
+                .and_then(|it| it.permissions)
+                .unwrap_or_else(Permissions::empty)
+        }
+    }
+}
+
+fn is_allowed(
+    interaction: &InteractionCreate,
+    context: &ChairContext,
+    access: CommandAccess,
+) -> Result<bool> {
+    let user = match interaction.author_id() {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+
+    if context.config.owner_ids.contains(&user) {
+        return Ok(true);
+    }
+
+    let required = match access {
+        CommandAccess::Everyone => return Ok(true),
+        CommandAccess::Owner => return Ok(false),
+        CommandAccess::Permissions(v) => v,
     };
 
-    if let Err(cause) = handle_command(*interaction, data, context).await {
-        warn!(?cause, "failed to execute command");
+    if context.users.is_administrator(user)? {
+        return Ok(true);
     }
+
+    let permissions = member_permissions(interaction, context);
+    Ok(permissions.contains(Permissions::ADMINISTRATOR) || permissions.contains(required))
 }
 
 async fn handle_command(
@@ -32,44 +132,486 @@ async fn handle_command(
     data: CommandData,
     context: Arc<ChairContext>,
 ) -> Result<()> {
+    if !is_allowed(&interaction, &context, command_access(&data.name))? {
+        let embed = simple_embed(
+            0xff3030,
+            "Not allowed",
+            "You don't have permission to use this command",
+        )?;
+        context
+            .discord
+            .create_response(
+                interaction.id,
+                &interaction.token,
+                embed_response(embed, true),
+            )
+            .await
+            .context("responding to disallowed command")?;
+        return Ok(());
+    }
+
     match data.name.as_str() {
         "ping" => PingCommand::handle(interaction, context).await?,
         "lfgdata" => LFGDataCommand::handle(interaction, data, context).await?,
+        "administrator" => AdministratorCommand::handle(interaction, data, context).await?,
+        "link" => LinkCommand::handle(interaction, data, context).await?,
+        "lfg" => LFGCommand::handle(interaction, data, context).await?,
         name => bail!("unknown command {name}"),
     }
 
     Ok(())
 }
 
-pub async fn register_commands(
-    client: &twilight_http::Client,
-    application: &Application,
+/// The name and current value of the option the user is typing in
+fn focused_option(options: &[CommandDataOption]) -> Option<(&str, &str)> {
+    options.iter().find_map(|it| match &it.value {
+        CommandOptionValue::Focused(value, _) => Some((it.name.as_str(), value.as_str())),
+        CommandOptionValue::SubCommand(options) | CommandOptionValue::SubCommandGroup(options) => {
+            focused_option(options)
+        }
+        _ => None,
+    })
+}
+
+async fn handle_autocomplete(
+    interaction: InteractionCreate,
+    data: CommandData,
+    context: Arc<ChairContext>,
+) -> Result<()> {
+    let (option, partial) = focused_option(&data.options).context("no focused option")?;
+
+    let choices = match (data.name.as_str(), option) {
+        ("lfg", "role") | ("lfgdata", "facade") => match interaction.guild_id {
+            Some(guild) => lfg_role_choices(&context, guild, partial)?,
+            None => Vec::new(),
+        },
+        (name, option) => bail!("unknown autocomplete {name} {option}"),
+    };
+
+    let response = InteractionResponse {
+        kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .choices(choices)
+                .build(),
+        ),
+    };
+
+    context
+        .discord
+        .create_response(interaction.id, &interaction.token, response)
+        .await
+        .context("responding to autocomplete")?;
+
+    Ok(())
+}
+
+async fn handle_component(
+    interaction: InteractionCreate,
+    data: MessageComponentInteractionData,
+    context: Arc<ChairContext>,
 ) -> Result<()> {
-    let commands = [
+    let custom_id = data.custom_id.as_str();
+
+    if let Some(session_id) = custom_id.strip_prefix("lfg-cancel-") {
+        let session_id = Uuid::parse_str(session_id).context("parsing lfg session id")?;
+        context
+            .lfg
+            .cancel_session(context.clone(), interaction, session_id)
+            .await?;
+        return Ok(());
+    }
+
+    if let Some(session_id) = custom_id.strip_prefix("lfg-leave-") {
+        let session_id = Uuid::parse_str(session_id).context("parsing lfg session id")?;
+        context
+            .lfg
+            .leave_session(context.clone(), interaction, session_id)
+            .await?;
+        return Ok(());
+    }
+
+    if let Some(session_id) = custom_id.strip_prefix("lfg-") {
+        let session_id = Uuid::parse_str(session_id).context("parsing lfg session id")?;
+        context
+            .lfg
+            .join_session(context.clone(), interaction, session_id)
+            .await?;
+        return Ok(());
+    }
+
+    bail!("unknown component {custom_id}")
+}
+
+fn wanted_commands(features: Features) -> Vec<Command> {
+    let mut commands = vec![
         PingCommand::create_command().into(),
-        LFGDataCommand::create_command().into(),
+        AdministratorCommand::create_command().into(),
     ];
-    let interaction_client = client.interaction(application.id);
 
-    let guilds = client
-        .current_user_guilds()
+    if features.lfg {
+        commands.push(LFGDataCommand::create_command().into());
+        commands.push(LFGCommand::create_command().into());
+    }
+
+    if features.links {
+        commands.push(LinkCommand::create_command().into());
+    }
+
+    commands
+}
+
+fn normalize_option(option: &CommandOption) -> CommandOption {
+    CommandOption {
+        autocomplete: option.autocomplete.filter(|it| *it),
+        required: option.required.filter(|it| *it),
+        channel_types: option.channel_types.clone().filter(|it| !it.is_empty()),
+        choices: option.choices.clone().filter(|it| !it.is_empty()),
+        description_localizations: option
+            .description_localizations
+            .clone()
+            .filter(|it| !it.is_empty()),
+        name_localizations: option
+            .name_localizations
+            .clone()
+            .filter(|it| !it.is_empty()),
+        options: option
+            .options
+            .as_ref()
+            .map(|it| it.iter().map(normalize_option).collect::<Vec<_>>())
+            .filter(|it| !it.is_empty()),
+        ..option.clone()
+    }
+}
+
+/// Strips what Discord fills in by itself, so a registered command compares equal to the
+/// command it was registered from
+fn normalize(command: &Command, in_guild: bool) -> Command {
+    Command {
+        application_id: None,
+        guild_id: None,
+        id: None,
+        version: Id::new(1),
+        // dm permission means nothing for guild commands and defaults to true
+        dm_permission: command.dm_permission.filter(|it| !in_guild && !*it),
+        nsfw: command.nsfw.filter(|it| *it),
+        description_localizations: command
+            .description_localizations
+            .clone()
+            .filter(|it| !it.is_empty()),
+        name_localizations: command
+            .name_localizations
+            .clone()
+            .filter(|it| !it.is_empty()),
+        options: command.options.iter().map(normalize_option).collect(),
+        ..command.clone()
+    }
+}
+
+/// Whether the registered commands are exactly the wanted ones, ignoring order
+fn commands_match(registered: &[Command], wanted: &[Command], in_guild: bool) -> bool {
+    let normalized = |commands: &[Command]| {
+        commands
+            .iter()
+            .map(|it| normalize(it, in_guild))
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect_vec()
+    };
+
+    normalized(registered) == normalized(wanted)
+}
+
+async fn sync_global_commands(client: &InteractionClient<'_>, features: Features) -> Result<()> {
+    let wanted = wanted_commands(features);
+    let registered = client
+        .global_commands()
         .await
-        .context("fetching guilds")?
-        .model()
+        .context("fetching global commands")?
+        .models()
         .await?;
 
-    for guild in &guilds {
-        interaction_client
-            .set_guild_commands(guild.id, &commands)
-            .await
-            .with_context(|| format!("setting guild commands for {}", guild.id))?;
+    if commands_match(&registered, &wanted, false) {
+        info!("global commands are up to date");
+        return Ok(());
     }
 
-    info!(
-        "registered {} commands in {} guilds",
-        commands.len(),
-        guilds.len()
-    );
+    client
+        .set_global_commands(&wanted)
+        .await
+        .context("setting global commands")?;
+
+    info!("registered {} global commands", wanted.len());
 
     Ok(())
 }
+
+async fn sync_guild_commands(
+    client: &InteractionClient<'_>,
+    guild: Id<GuildMarker>,
+    features: Features,
+) -> Result<()> {
+    let wanted = wanted_commands(features);
+    let registered = client
+        .guild_commands(guild)
+        .await
+        .with_context(|| format!("fetching guild commands for {guild}"))?
+        .models()
+        .await?;
+
+    if commands_match(&registered, &wanted, true) {
+        debug!(%guild, "guild commands are up to date");
+        return Ok(());
+    }
+
+    client
+        .set_guild_commands(guild, &wanted)
+        .await
+        .with_context(|| format!("setting guild commands for {guild}"))?;
+
+    info!(%guild, "registered {} guild commands", wanted.len());
+
+    Ok(())
+}
+
+/// Removes global commands left over from running in global mode
+async fn clear_global_commands(client: &InteractionClient<'_>) -> Result<()> {
+    let registered = client
+        .global_commands()
+        .await
+        .context("fetching global commands")?
+        .models()
+        .await?;
+
+    if registered.is_empty() {
+        return Ok(());
+    }
+
+    client
+        .set_global_commands(&[])
+        .await
+        .context("clearing global commands")?;
+
+    info!("removed {} stale global commands", registered.len());
+
+    Ok(())
+}
+
+/// Removes guild commands left over from registering per guild
+async fn clear_guild_commands(
+    client: &InteractionClient<'_>,
+    guild: Id<GuildMarker>,
+) -> Result<()> {
+    let registered = client
+        .guild_commands(guild)
+        .await
+        .with_context(|| format!("fetching guild commands for {guild}"))?
+        .models()
+        .await?;
+
+    if registered.is_empty() {
+        return Ok(());
+    }
+
+    client
+        .set_guild_commands(guild, &[])
+        .await
+        .with_context(|| format!("clearing guild commands for {guild}"))?;
+
+    info!(%guild, "removed {} stale guild commands", registered.len());
+
+    Ok(())
+}
+
+/// Registers commands on startup, per guild registration waits for guilds to come in.
+/// Commands registered the other way are removed so they don't show up twice
+pub async fn register_commands(
+    client: &twilight_http::Client,
+    application: &Application,
+    mode: RegistrationMode,
+    features: Features,
+) -> Result<()> {
+    let interaction_client = client.interaction(application.id);
+
+    match mode {
+        RegistrationMode::Global => sync_global_commands(&interaction_client, features).await,
+        RegistrationMode::DevGuild(guild) => {
+            sync_guild_commands(&interaction_client, guild, features).await?;
+            clear_global_commands(&interaction_client).await
+        }
+        RegistrationMode::PerGuild => {
+            info!("registering commands as guilds are created");
+            clear_global_commands(&interaction_client).await
+        }
+    }
+}
+
+/// Registers commands in a guild the bot is in for per guild registration, otherwise
+/// removes the ones an earlier per guild run left behind
+pub async fn on_guild_create(context: Arc<ChairContext>, guild: Id<GuildMarker>) -> Result<()> {
+    let client = context.interaction_client();
+
+    match context.config.registration_mode {
+        RegistrationMode::PerGuild => {
+            sync_guild_commands(&client, guild, context.config.features).await
+        }
+        // registered on startup
+        RegistrationMode::DevGuild(dev_guild) if dev_guild == guild => Ok(()),
+        _ => clear_guild_commands(&client, guild).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use twilight_model::{
+        application::interaction::Interaction, gateway::payload::incoming::InteractionCreate,
+        http::interaction::InteractionResponseType, id::Id,
+    };
+    use uuid::Uuid;
+
+    use super::{command_handle_interaction, commands_match, wanted_commands};
+    use crate::{
+        config::Features,
+        discord::{
+            fake::{Action, RecordingActions},
+            MessageEdit,
+        },
+        models::fake,
+        profile::fake::FakeProfileResolver,
+        util::{embed_response, simple_embed},
+    };
+
+    fn command(id: u64, data: serde_json::Value) -> Box<InteractionCreate> {
+        let interaction: Interaction = serde_json::from_value(serde_json::json!({
+            "id": id.to_string(),
+            "application_id": "2",
+            "type": 2,
+            "token": "token",
+            "version": 1,
+            "guild_id": "1",
+            "channel_id": "50",
+            "user": {
+                "id": "100",
+                "username": "user100",
+                "discriminator": "0001",
+                "avatar": null,
+            },
+            "data": data,
+        }))
+        .unwrap();
+        Box::new(InteractionCreate(interaction))
+    }
+
+    #[tokio::test]
+    async fn members_without_permission_are_turned_away() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = fake::context(actions.clone(), Arc::default());
+
+        let data = serde_json::json!({ "id": "3", "name": "lfgdata", "type": 1 });
+        command_handle_interaction(command(400, data), context).await;
+
+        assert_eq!(
+            actions.take().await,
+            [Action::Respond {
+                interaction: Id::new(400),
+                response: embed_response(
+                    simple_embed(
+                        0xff3030,
+                        "Not allowed",
+                        "You don't have permission to use this command"
+                    )
+                    .unwrap(),
+                    true
+                ),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn link_add_answers_after_deferring() {
+        let actions = Arc::new(RecordingActions::default());
+        let profiles = Arc::new(FakeProfileResolver::default());
+        profiles.insert(Uuid::new_v4(), "Notch").await;
+        let context = fake::context(actions.clone(), profiles);
+
+        let link_add = |account: &str| {
+            serde_json::json!({
+                "id": "3",
+                "name": "link",
+                "type": 1,
+                "options": [{
+                    "name": "add",
+                    "type": 1,
+                    "options": [{ "name": "account", "type": 3, "value": account }],
+                }],
+            })
+        };
+
+        let answered = |title: &str, description: &str| Action::UpdateResponse {
+            token: "token".to_owned(),
+            edit: MessageEdit {
+                embeds: Some(vec![simple_embed(0xff3030, title, description).unwrap()]),
+                ..Default::default()
+            },
+        };
+
+        command_handle_interaction(command(400, link_add("nobody")), context.clone()).await;
+        let done = actions.take().await;
+        assert!(matches!(
+            &done[0],
+            Action::Respond { interaction, response }
+                if *interaction == Id::new(400)
+                    && response.kind == InteractionResponseType::DeferredChannelMessageWithSource
+        ));
+        assert_eq!(
+            done[1..],
+            [answered(
+                "Unknown account",
+                "There is no account called `nobody`"
+            )]
+        );
+
+        command_handle_interaction(command(401, link_add("notch")), context.clone()).await;
+        let done = actions.take().await;
+        assert_eq!(done.len(), 2);
+        assert!(matches!(
+            &done[1],
+            Action::UpdateResponse { edit, .. }
+                if edit.embeds.as_ref().unwrap()[0].title.as_deref() == Some("Linked account")
+        ));
+        assert_eq!(
+            context
+                .users
+                .main_username(Id::new(100))
+                .unwrap()
+                .as_deref(),
+            Some("Notch")
+        );
+    }
+
+    #[test]
+    fn registered_commands_match_wanted() {
+        let wanted = wanted_commands(Features::default());
+
+        // what Discord hands back has ids and spells out its defaults
+        let mut registered = wanted.clone();
+        registered.reverse();
+        for command in &mut registered {
+            command.id = Some(Id::new(1));
+            command.application_id = Some(Id::new(2));
+            command.version = Id::new(3);
+            command.dm_permission = command.dm_permission.or(Some(true));
+            command.nsfw = Some(false);
+        }
+        assert!(commands_match(&registered, &wanted, false));
+        assert!(commands_match(&registered, &wanted, true));
+
+        registered[0].description += "!";
+        assert!(!commands_match(&registered, &wanted, false));
+
+        registered.remove(0);
+        assert!(!commands_match(&registered, &wanted[1..], false));
+        assert!(!commands_match(&registered, &wanted, false));
+    }
+}
@@ -1,8 +1,441 @@
-use env_struct::env_struct;
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
 
-env_struct! {
-    #[derive(Clone)]
-    pub struct ChairConfig {
-        pub bot_token,
+use anyhow::{anyhow, Context, Result};
+use twilight_cache_inmemory::ResourceType;
+use twilight_gateway::Intents;
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
+
+use crate::lfg::ShutdownPolicy;
+
+/// Where slash commands get registered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegistrationMode {
+    /// once for every guild, changes can take a while to show up
+    Global,
+    /// only in one guild, changes show up straight away
+    DevGuild(Id<GuildMarker>),
+    /// in each guild as the bot sees it
+    PerGuild,
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "full" => Ok(LogFormat::Full),
+            "compact" => Ok(LogFormat::Compact),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err(anyhow!("expected `full`, `compact` or `pretty`")),
+        }
+    }
+}
+
+/// Parts of the bot that can be switched off
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    /// LFG pings, `/lfg` and `/lfgdata`
+    pub lfg: bool,
+    /// `/link` and linked names in pings
+    pub links: bool,
+    /// renaming links whose game name changed, needs links
+    pub link_refresh: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            lfg: true,
+            links: true,
+            link_refresh: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChairConfig {
+    pub bot_token: String,
+    /// the bot owners, who may run every command
+    pub owner_ids: Vec<Id<UserMarker>>,
+    pub database_path: PathBuf,
+    /// the guild mention types from before guilds were tracked belong to
+    pub legacy_guild_id: Option<Id<GuildMarker>>,
+    pub registration_mode: RegistrationMode,
+    /// how long pings of newly added LFG types last
+    pub default_expiry_minutes: u32,
+    pub log_format: LogFormat,
+    /// Mojang-style profile lookups, `{}` is replaced by the username or UUID
+    pub profile_by_username_url: String,
+    pub profile_by_uuid_url: String,
+    pub shutdown_policy: ShutdownPolicy,
+    /// how long in-flight events get to finish once the bot is asked to stop
+    pub shutdown_timeout: Duration,
+    pub features: Features,
+}
+
+/// Every problem found in the configuration, so they can all be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} problem(s) with the configuration",
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The config file with environment variables on top. `shutdown.policy` in the file is
+/// overridden by `SHUTDOWN_POLICY` in the environment
+struct Layers<'a> {
+    file: toml::Table,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    problems: Vec<String>,
+}
+
+fn env_name(key: &str) -> String {
+    key.replace('.', "_").to_uppercase()
+}
+
+impl Layers<'_> {
+    fn text(&mut self, key: &str) -> Option<String> {
+        if let Some(value) = (self.env)(&env_name(key)) {
+            return Some(value);
+        }
+
+        let mut parts = key.split('.');
+        let first = parts.next()?;
+        let mut value = self.file.get(first)?;
+        for part in parts {
+            value = value.get(part)?;
+        }
+
+        let scalar = |value: &toml::Value| match value {
+            toml::Value::String(v) => Some(v.clone()),
+            toml::Value::Integer(v) => Some(v.to_string()),
+            toml::Value::Float(v) => Some(v.to_string()),
+            toml::Value::Boolean(v) => Some(v.to_string()),
+            _ => None,
+        };
+
+        let text = match value {
+            // lists read the same as a comma separated environment variable
+            toml::Value::Array(values) => values.iter().map(scalar).collect::<Option<Vec<_>>>(),
+            other => scalar(other).map(|it| vec![it]),
+        };
+
+        match text {
+            Some(v) => Some(v.join(",")),
+            None => {
+                self.problems
+                    .push(format!("`{key}` has to be a plain value"));
+                None
+            }
+        }
+    }
+
+    fn parse<T>(&mut self, key: &str, text: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match text.trim().parse() {
+            Ok(v) => Some(v),
+            Err(cause) => {
+                self.problems
+                    .push(format!("`{key}` ({}) is invalid: {cause}", env_name(key)));
+                None
+            }
+        }
+    }
+
+    fn required<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.text(key).filter(|it| !it.trim().is_empty()) {
+            Some(text) => self.parse(key, &text),
+            None => {
+                self.problems
+                    .push(format!("`{key}` ({}) is missing", env_name(key)));
+                None
+            }
+        }
+    }
+
+    /// Falls back to the default when missing, and when invalid after noting the problem
+    fn value<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.text(key).filter(|it| !it.trim().is_empty()) {
+            Some(text) => self.parse(key, &text).unwrap_or(default),
+            None => default,
+        }
+    }
+
+    fn optional<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let text = self.text(key).filter(|it| !it.trim().is_empty())?;
+        self.parse(key, &text)
+    }
+
+    fn list<T>(&mut self, key: &str) -> Vec<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let text = self.text(key).unwrap_or_default();
+        text.split(',')
+            .filter(|it| !it.trim().is_empty())
+            .filter_map(|it| self.parse(key, it))
+            .collect()
+    }
+
+    fn registration_mode(&mut self) -> Option<RegistrationMode> {
+        let mode = self.value("registration.mode", "global".to_owned());
+        match mode.trim().to_lowercase().as_str() {
+            "global" => Some(RegistrationMode::Global),
+            "guild" => self
+                .required("registration.dev_guild_id")
+                .map(RegistrationMode::DevGuild),
+            "per_guild" => Some(RegistrationMode::PerGuild),
+            other => {
+                self.problems.push(format!(
+                    "`registration.mode` (REGISTRATION_MODE) is invalid: expected `global`, `guild` or `per_guild`, not `{other}`"
+                ));
+                None
+            }
+        }
+    }
+}
+
+impl ChairConfig {
+    /// Loads `chair.toml`, or the file in `CHAIR_CONFIG`, with the environment on top
+    pub fn load() -> Result<Self> {
+        let (path, explicit) = match std::env::var("CHAIR_CONFIG") {
+            Ok(v) => (PathBuf::from(v), true),
+            Err(_) => (PathBuf::from("chair.toml"), false),
+        };
+
+        // the file is optional unless it was asked for
+        let file = match std::fs::read_to_string(&path) {
+            Ok(v) => v,
+            Err(cause) if !explicit && cause.kind() == std::io::ErrorKind::NotFound => {
+                String::new()
+            }
+            Err(cause) => return Err(cause).with_context(|| format!("reading {}", path.display())),
+        };
+
+        Ok(Self::from_layers(&file, &|key| std::env::var(key).ok())?)
+    }
+
+    pub fn from_layers(
+        file: &str,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> std::result::Result<Self, ConfigError> {
+        let file = file.parse::<toml::Table>().map_err(|cause| ConfigError {
+            problems: vec![format!("the config file is invalid: {cause}")],
+        })?;
+
+        let mut layers = Layers {
+            file,
+            env,
+            problems: Vec::new(),
+        };
+
+        let bot_token = layers.required::<String>("bot_token");
+        let owner_ids = layers.list("owner_ids");
+        let database_path = layers.value("database_path", PathBuf::from("chair.sled"));
+        let legacy_guild_id = layers.optional("storage.legacy_guild_id");
+        let registration_mode = layers.registration_mode();
+        let default_expiry_minutes = layers.value("lfg.default_expiry_minutes", 30);
+        let log_format = layers.value("log_format", LogFormat::Full);
+        let profile_by_username_url = layers.value(
+            "profiles.by_username_url",
+            "https://api.mojang.com/users/profiles/minecraft/{}".to_owned(),
+        );
+        let profile_by_uuid_url = layers.value(
+            "profiles.by_uuid_url",
+            "https://sessionserver.mojang.com/session/minecraft/profile/{}".to_owned(),
+        );
+        let shutdown_policy = layers.value("shutdown.policy", ShutdownPolicy::Persist);
+        let shutdown_timeout = Duration::from_secs(layers.value("shutdown.timeout_seconds", 10));
+
+        let defaults = Features::default();
+        let features = Features {
+            lfg: layers.value("features.lfg", defaults.lfg),
+            links: layers.value("features.links", defaults.links),
+            link_refresh: layers.value("features.link_refresh", defaults.link_refresh),
+        };
+
+        if default_expiry_minutes == 0 {
+            layers
+                .problems
+                .push("`lfg.default_expiry_minutes` has to be at least 1".to_owned());
+        }
+
+        for (key, url) in [
+            ("profiles.by_username_url", &profile_by_username_url),
+            ("profiles.by_uuid_url", &profile_by_uuid_url),
+        ] {
+            if features.links && !url.contains("{}") {
+                layers
+                    .problems
+                    .push(format!("`{key}` needs a `{{}}` to put the account in"));
+            }
+        }
+
+        match (bot_token, registration_mode) {
+            (Some(bot_token), Some(registration_mode)) if layers.problems.is_empty() => {
+                Ok(ChairConfig {
+                    bot_token,
+                    owner_ids,
+                    database_path,
+                    legacy_guild_id,
+                    registration_mode,
+                    default_expiry_minutes,
+                    log_format,
+                    profile_by_username_url,
+                    profile_by_uuid_url,
+                    shutdown_policy,
+                    shutdown_timeout,
+                    features,
+                })
+            }
+            _ => Err(ConfigError {
+                problems: layers.problems,
+            }),
+        }
+    }
+
+    pub fn intents(&self) -> Intents {
+        let mut intents = Intents::GUILDS;
+        if self.features.lfg {
+            intents |= Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT;
+        }
+        intents
+    }
+
+    pub fn resource_types(&self) -> ResourceType {
+        let mut resource_types =
+            ResourceType::USER | ResourceType::GUILD | ResourceType::ROLE | ResourceType::MEMBER;
+        if self.features.lfg {
+            resource_types |= ResourceType::MESSAGE;
+        }
+        resource_types
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use twilight_model::id::Id;
+
+    use super::{ChairConfig, LogFormat, RegistrationMode};
+    use crate::lfg::ShutdownPolicy;
+
+    /// Environment variables as if they were set
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let file = r#"
+            bot_token = "from file"
+            owner_ids = [1, 2]
+            log_format = "compact"
+
+            [registration]
+            mode = "guild"
+            dev_guild_id = 5
+
+            [shutdown]
+            policy = "expire"
+
+            [features]
+            links = false
+        "#;
+        let env = env_from(&[("BOT_TOKEN", "from env"), ("SHUTDOWN_TIMEOUT_SECONDS", "3")]);
+
+        let config = ChairConfig::from_layers(file, &env).unwrap();
+        assert_eq!(config.bot_token, "from env");
+        assert_eq!(config.owner_ids, [Id::new(1), Id::new(2)]);
+        assert_eq!(config.log_format, LogFormat::Compact);
+        assert_eq!(
+            config.registration_mode,
+            RegistrationMode::DevGuild(Id::new(5))
+        );
+        assert_eq!(config.shutdown_policy, ShutdownPolicy::Expire);
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(3));
+        assert_eq!(config.default_expiry_minutes, 30);
+        assert!(config.features.lfg && !config.features.links);
+
+        let env = env_from(&[("BOT_TOKEN", "token"), ("OWNER_IDS", "7, 8")]);
+        let config = ChairConfig::from_layers("", &env).unwrap();
+        assert_eq!(config.owner_ids, [Id::new(7), Id::new(8)]);
+        assert_eq!(config.registration_mode, RegistrationMode::Global);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let file = r#"
+            owner_ids = ["someone"]
+
+            [registration]
+            mode = "guild"
+
+            [lfg]
+            default_expiry_minutes = 0
+        "#;
+        let env = env_from(&[("SHUTDOWN_POLICY", "forget"), ("FEATURES_LFG", "maybe")]);
+
+        let problems = ChairConfig::from_layers(file, &env).unwrap_err().problems;
+        for key in [
+            "bot_token",
+            "owner_ids",
+            "registration.dev_guild_id",
+            "lfg.default_expiry_minutes",
+            "shutdown.policy",
+            "features.lfg",
+        ] {
+            assert!(
+                problems.iter().any(|it| it.contains(key)),
+                "{key} missing from {problems:#?}"
+            );
+        }
+        assert_eq!(problems.len(), 6, "{problems:#?}");
     }
 }
@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use twilight_http::request::AuditLogReason;
+use twilight_model::{
+    channel::message::{AllowedMentions, Component, Embed},
+    http::interaction::InteractionResponse,
+    id::{
+        marker::{ApplicationMarker, ChannelMarker, InteractionMarker, MessageMarker},
+        Id,
+    },
+};
+
+/// A message to send, as a reply when `reply` is set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NewMessage {
+    pub reply: Option<Id<MessageMarker>>,
+    pub content: Option<String>,
+    pub embeds: Vec<Embed>,
+    pub components: Vec<Component>,
+    /// the client's default is used without one
+    pub allowed_mentions: Option<AllowedMentions>,
+}
+
+/// Changes to a sent message, whatever is `None` is left as it is
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageEdit {
+    /// `Some(None)` removes the content
+    pub content: Option<Option<String>>,
+    pub embeds: Option<Vec<Embed>>,
+    pub components: Option<Vec<Component>>,
+    pub allowed_mentions: Option<AllowedMentions>,
+}
+
+/// What the bot does to Discord, so pings can play out without a connection
+#[async_trait]
+pub trait DiscordActions: Send + Sync {
+    /// Returns the id of the sent message
+    async fn create_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        message: NewMessage,
+    ) -> Result<Id<MessageMarker>>;
+    async fn update_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        message: Id<MessageMarker>,
+        edit: MessageEdit,
+    ) -> Result<()>;
+    async fn delete_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        message: Id<MessageMarker>,
+        reason: &str,
+    ) -> Result<()>;
+    async fn create_response(
+        &self,
+        interaction: Id<InteractionMarker>,
+        token: &str,
+        response: InteractionResponse,
+    ) -> Result<()>;
+    /// The message an interaction response sent
+    async fn response_message(&self, token: &str) -> Result<Id<MessageMarker>>;
+    /// Fills in or changes a response, like one that was deferred
+    async fn update_response(&self, token: &str, edit: MessageEdit) -> Result<()>;
+}
+
+/// Sends everything through the twilight http client
+pub struct HttpActions {
+    http: Arc<twilight_http::Client>,
+    application_id: Id<ApplicationMarker>,
+}
+
+impl HttpActions {
+    pub fn new(http: Arc<twilight_http::Client>, application_id: Id<ApplicationMarker>) -> Self {
+        HttpActions {
+            http,
+            application_id,
+        }
+    }
+}
+
+#[async_trait]
+impl DiscordActions for HttpActions {
+    async fn create_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        message: NewMessage,
+    ) -> Result<Id<MessageMarker>> {
+        let mut create = self
+            .http
+            .create_message(channel)
+            .embeds(&message.embeds)
+            .context("setting embeds")?
+            .components(&message.components)
+            .context("setting components")?;
+
+        if let Some(reply) = message.reply {
+            create = create.reply(reply);
+        }
+        if let Some(content) = &message.content {
+            create = create.content(content).context("setting content")?;
+        }
+        if let Some(allowed_mentions) = &message.allowed_mentions {
+            create = create.allowed_mentions(Some(allowed_mentions));
+        }
+
+        let sent = create.await?.model().await?;
+        Ok(sent.id)
+    }
+
+    async fn update_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        message: Id<MessageMarker>,
+        edit: MessageEdit,
+    ) -> Result<()> {
+        let mut update = self.http.update_message(channel, message);
+
+        if let Some(content) = &edit.content {
+            update = update
+                .content(content.as_deref())
+                .context("setting content")?;
+        }
+        if let Some(embeds) = &edit.embeds {
+            update = update.embeds(Some(embeds)).context("setting embeds")?;
+        }
+        if let Some(components) = &edit.components {
+            update = update
+                .components(Some(components))
+                .context("setting components")?;
+        }
+        if let Some(allowed_mentions) = &edit.allowed_mentions {
+            update = update.allowed_mentions(Some(allowed_mentions));
+        }
+
+        update.await?;
+        Ok(())
+    }
+
+    async fn delete_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        message: Id<MessageMarker>,
+        reason: &str,
+    ) -> Result<()> {
+        self.http
+            .delete_message(channel, message)
+            .reason(reason)
+            .context("setting audit log reason")?
+            .await?;
+        Ok(())
+    }
+
+    async fn create_response(
+        &self,
+        interaction: Id<InteractionMarker>,
+        token: &str,
+        response: InteractionResponse,
+    ) -> Result<()> {
+        self.http
+            .interaction(self.application_id)
+            .create_response(interaction, token, &response)
+            .await?;
+        Ok(())
+    }
+
+    async fn response_message(&self, token: &str) -> Result<Id<MessageMarker>> {
+        let message = self
+            .http
+            .interaction(self.application_id)
+            .response(token)
+            .await?
+            .model()
+            .await?;
+        Ok(message.id)
+    }
+
+    async fn update_response(&self, token: &str, edit: MessageEdit) -> Result<()> {
+        let client = self.http.interaction(self.application_id);
+        let mut update = client.update_response(token);
+
+        if let Some(content) = &edit.content {
+            update = update
+                .content(content.as_deref())
+                .context("setting content")?;
+        }
+        if let Some(embeds) = &edit.embeds {
+            update = update.embeds(Some(embeds)).context("setting embeds")?;
+        }
+        if let Some(components) = &edit.components {
+            update = update
+                .components(Some(components))
+                .context("setting components")?;
+        }
+        if let Some(allowed_mentions) = &edit.allowed_mentions {
+            update = update.allowed_mentions(Some(allowed_mentions));
+        }
+
+        update.await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use tokio::sync::RwLock;
+    use twilight_model::{
+        http::interaction::InteractionResponse,
+        id::{
+            marker::{ChannelMarker, InteractionMarker, MessageMarker},
+            Id,
+        },
+    };
+
+    use super::{DiscordActions, MessageEdit, NewMessage};
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Action {
+        Create {
+            channel: Id<ChannelMarker>,
+            id: Id<MessageMarker>,
+            message: NewMessage,
+        },
+        Update {
+            channel: Id<ChannelMarker>,
+            message: Id<MessageMarker>,
+            edit: MessageEdit,
+        },
+        Delete {
+            channel: Id<ChannelMarker>,
+            message: Id<MessageMarker>,
+            reason: String,
+        },
+        Respond {
+            interaction: Id<InteractionMarker>,
+            response: InteractionResponse,
+        },
+        UpdateResponse {
+            token: String,
+            edit: MessageEdit,
+        },
+    }
+
+    /// Keeps every action in order instead of sending it, sent messages and fetched
+    /// responses get increasing ids starting at 1000
+    #[derive(Default)]
+    pub struct RecordingActions {
+        pub actions: RwLock<Vec<Action>>,
+        sent: AtomicU64,
+        responses: RwLock<HashMap<String, Id<MessageMarker>>>,
+    }
+
+    impl RecordingActions {
+        /// Everything done since the last call
+        pub async fn take(&self) -> Vec<Action> {
+            std::mem::take(&mut *self.actions.write().await)
+        }
+
+        fn next_id(&self) -> Id<MessageMarker> {
+            Id::new(1000 + self.sent.fetch_add(1, Ordering::SeqCst))
+        }
+    }
+
+    #[async_trait]
+    impl DiscordActions for RecordingActions {
+        async fn create_message(
+            &self,
+            channel: Id<ChannelMarker>,
+            message: NewMessage,
+        ) -> Result<Id<MessageMarker>> {
+            let id = self.next_id();
+            self.actions.write().await.push(Action::Create {
+                channel,
+                id,
+                message,
+            });
+            Ok(id)
+        }
+
+        async fn update_message(
+            &self,
+            channel: Id<ChannelMarker>,
+            message: Id<MessageMarker>,
+            edit: MessageEdit,
+        ) -> Result<()> {
+            self.actions.write().await.push(Action::Update {
+                channel,
+                message,
+                edit,
+            });
+            Ok(())
+        }
+
+        async fn delete_message(
+            &self,
+            channel: Id<ChannelMarker>,
+            message: Id<MessageMarker>,
+            reason: &str,
+        ) -> Result<()> {
+            self.actions.write().await.push(Action::Delete {
+                channel,
+                message,
+                reason: reason.to_owned(),
+            });
+            Ok(())
+        }
+
+        async fn create_response(
+            &self,
+            interaction: Id<InteractionMarker>,
+            _token: &str,
+            response: InteractionResponse,
+        ) -> Result<()> {
+            self.actions.write().await.push(Action::Respond {
+                interaction,
+                response,
+            });
+            Ok(())
+        }
+
+        async fn response_message(&self, token: &str) -> Result<Id<MessageMarker>> {
+            let mut responses = self.responses.write().await;
+            if let Some(id) = responses.get(token) {
+                return Ok(*id);
+            }
+
+            let id = self.next_id();
+            responses.insert(token.to_owned(), id);
+            Ok(id)
+        }
+
+        async fn update_response(&self, token: &str, edit: MessageEdit) -> Result<()> {
+            self.actions.write().await.push(Action::UpdateResponse {
+                token: token.to_owned(),
+                edit,
+            });
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use tokio::{
+    sync::{Mutex, Notify},
+    time::{self, Instant},
+};
+use uuid::Uuid;
+
+#[derive(Default)]
+struct ExpiryQueue {
+    queue: BTreeSet<(Instant, Uuid)>,
+    deadlines: HashMap<Uuid, Instant>,
+}
+
+/// Time-ordered queue of session deadlines, meant to be drained by a single task
+/// through [`ExpiryScheduler::next_due`]
+#[derive(Default)]
+pub struct ExpiryScheduler {
+    state: Mutex<ExpiryQueue>,
+    notify: Notify,
+}
+
+/// Deadlines are kept on tokio's clock so waiting on them follows it, past ones are due now
+fn instant_at(at: DateTime<Utc>) -> Instant {
+    let now = Instant::now();
+    match (at - Utc::now()).to_std() {
+        Ok(v) => now + v,
+        Err(_) => now,
+    }
+}
+
+impl ExpiryScheduler {
+    pub fn new(deadlines: impl IntoIterator<Item = (Uuid, DateTime<Utc>)>) -> Self {
+        let mut state = ExpiryQueue::default();
+        for (id, at) in deadlines {
+            let at = instant_at(at);
+            state.queue.insert((at, id));
+            state.deadlines.insert(id, at);
+        }
+
+        ExpiryScheduler {
+            state: Mutex::new(state),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Sets the deadline of `id`, replacing (extending or shortening) any existing one
+    pub async fn schedule(&self, id: Uuid, at: DateTime<Utc>) {
+        let at = instant_at(at);
+
+        let mut state = self.state.lock().await;
+        if let Some(previous) = state.deadlines.insert(id, at) {
+            state.queue.remove(&(previous, id));
+        }
+        state.queue.insert((at, id));
+        drop(state);
+
+        self.notify.notify_one();
+    }
+
+    pub async fn cancel(&self, id: Uuid) {
+        let mut state = self.state.lock().await;
+        if let Some(previous) = state.deadlines.remove(&id) {
+            state.queue.remove(&(previous, id));
+        }
+        drop(state);
+
+        self.notify.notify_one();
+    }
+
+    /// Waits until the earliest deadline passes and hands back its id
+    pub async fn next_due(&self) -> Uuid {
+        loop {
+            // registered before peeking so a schedule in between still wakes us up
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let mut state = self.state.lock().await;
+            let next = state.queue.first().copied();
+
+            let wait = match next {
+                None => None,
+                Some((at, id)) => {
+                    if at <= Instant::now() {
+                        state.queue.remove(&(at, id));
+                        state.deadlines.remove(&id);
+                        return id;
+                    }
+
+                    Some(at)
+                }
+            };
+            drop(state);
+
+            match wait {
+                None => notified.await,
+                Some(at) => {
+                    tokio::select! {
+                        _ = time::sleep_until(at) => {}
+                        _ = notified => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::Utc;
+    use tokio::time::{self, Instant};
+    use uuid::Uuid;
+
+    use super::ExpiryScheduler;
+
+    fn in_secs(secs: i64) -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(secs)
+    }
+
+    /// The deadlines are taken from the wall clock, which keeps going while tokio's is paused
+    fn assert_elapsed(start: Instant, secs: u64) {
+        let elapsed = start.elapsed();
+        let expected = Duration::from_secs(secs);
+        assert!(
+            elapsed.max(expected) - elapsed.min(expected) < Duration::from_millis(100),
+            "expected {expected:?}, waited {elapsed:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fires_in_order() {
+        let (restored, first, second, third) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let scheduler = ExpiryScheduler::new([(restored, in_secs(-60))]);
+        let start = Instant::now();
+
+        scheduler.schedule(third, in_secs(30)).await;
+        scheduler.schedule(first, in_secs(10)).await;
+        scheduler.schedule(second, in_secs(20)).await;
+
+        // anything that passed while the bot was down is due straight away
+        assert_eq!(scheduler.next_due().await, restored);
+        assert_elapsed(start, 0);
+
+        assert_eq!(scheduler.next_due().await, first);
+        assert_elapsed(start, 10);
+        assert_eq!(scheduler.next_due().await, second);
+        assert_elapsed(start, 20);
+        assert_eq!(scheduler.next_due().await, third);
+        assert_elapsed(start, 30);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rescheduling_extends_and_shortens() {
+        let (extended, shortened, other) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let scheduler = ExpiryScheduler::default();
+        let start = Instant::now();
+
+        scheduler.schedule(extended, in_secs(10)).await;
+        scheduler.schedule(shortened, in_secs(60)).await;
+        scheduler.schedule(other, in_secs(20)).await;
+
+        scheduler.schedule(extended, in_secs(40)).await;
+        scheduler.schedule(shortened, in_secs(5)).await;
+
+        assert_eq!(scheduler.next_due().await, shortened);
+        assert_elapsed(start, 5);
+        assert_eq!(scheduler.next_due().await, other);
+        assert_elapsed(start, 20);
+        assert_eq!(scheduler.next_due().await, extended);
+        assert_elapsed(start, 40);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_deadlines_never_fire() {
+        let (cancelled, kept) = (Uuid::new_v4(), Uuid::new_v4());
+        let scheduler = ExpiryScheduler::default();
+        let start = Instant::now();
+
+        scheduler.schedule(cancelled, in_secs(10)).await;
+        scheduler.schedule(kept, in_secs(20)).await;
+        scheduler.cancel(cancelled).await;
+
+        assert_eq!(scheduler.next_due().await, kept);
+        assert_elapsed(start, 20);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduling_wakes_a_waiting_next_due() {
+        let (late, early) = (Uuid::new_v4(), Uuid::new_v4());
+        let scheduler = Arc::new(ExpiryScheduler::default());
+        let start = Instant::now();
+
+        let waiting = scheduler.clone();
+        let next = tokio::spawn(async move { waiting.next_due().await });
+
+        // waiting on an empty queue, then on a far deadline
+        time::sleep(Duration::from_secs(1)).await;
+        scheduler.schedule(late, in_secs(60)).await;
+        time::sleep(Duration::from_secs(1)).await;
+        scheduler.schedule(early, in_secs(3)).await;
+
+        assert_eq!(next.await.unwrap(), early);
+        assert_elapsed(start, 5);
+        assert_eq!(scheduler.next_due().await, late);
+        assert_elapsed(start, 61);
+    }
+}
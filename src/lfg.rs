@@ -1,51 +1,224 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use chrono::{Duration, Utc};
 use itertools::Itertools;
-use lazy_regex::regex_captures;
 use rand::{seq::SliceRandom, thread_rng};
-use sled::{Db, Tree};
-use tokio::{sync::RwLock, task::AbortHandle, time};
+use tokio::sync::RwLock;
 use tracing::{info, warn};
-use twilight_http::request::AuditLogReason;
+use twilight_cache_inmemory::InMemoryCache;
 use twilight_model::{
     channel::message::{
         component::{ActionRow, Button, ButtonStyle},
         AllowedMentions, Component, MentionType,
     },
-    gateway::payload::incoming::MessageCreate,
+    gateway::payload::incoming::{InteractionCreate, MessageCreate, MessageUpdate},
     id::{
-        marker::{MessageMarker, RoleMarker},
+        marker::{ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker},
         Id,
     },
 };
 use uuid::Uuid;
 
 use crate::{
-    models::{ChairContext, LFGSession},
-    util::{coerce_into_u64, simple_embed},
+    discord::{MessageEdit, NewMessage},
+    expiry::ExpiryScheduler,
+    models::{ChairContext, GuildSettings, LFGSession, LFGType, MultiRoleMode},
+    parser::{self, Count, ParsedPing},
+    storage::Storage,
+    util::{embed_response, simple_embed},
 };
 
-const EXPIRED_MESSAGES: [&str; 4] = [
-    "Shoot. We left it out too long, and the ping expired",
-    "Arena is dead and this unplayed ping proves it",
-    "Maybe the ping would fill up if wife came back",
-    "*Surely* next ping will fill up right?",
-];
-
-const BLANK_ALLOWED_MENTIONS: &AllowedMentions = &AllowedMentions {
-    replied_user: false,
-    parse: vec![],
-    roles: vec![],
-    users: vec![],
-};
+fn blank_allowed_mentions() -> AllowedMentions {
+    AllowedMentions {
+        replied_user: false,
+        parse: vec![],
+        roles: vec![],
+        users: vec![],
+    }
+}
+
+/// The parts of a created or edited message that matter for an LFG ping
+struct LFGMessage {
+    id: Id<MessageMarker>,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    author: Id<UserMarker>,
+    author_bot: bool,
+    content: String,
+    /// non-bot users mentioned in the message
+    mentions: Vec<Id<UserMarker>>,
+}
+
+fn linked_username(context: &ChairContext, user: Id<UserMarker>) -> Option<String> {
+    if !context.config.features.links {
+        return None;
+    }
+
+    match context.users.main_username(user) {
+        Ok(v) => v,
+        Err(cause) => {
+            warn!(?cause, %user, "error reading linked username");
+            None
+        }
+    }
+}
+
+/// A copyable party invite for everyone but the author who has a linked account
+fn party_invite(session: &LFGSession, members: &[(Id<UserMarker>, Option<String>)]) -> String {
+    let usernames = members
+        .iter()
+        .filter(|(id, _)| *id != session.author && !session.excluded_participants.contains(id))
+        .filter_map(|(_, username)| username.as_deref())
+        .join(" ");
+
+    if usernames.is_empty() {
+        return String::new();
+    }
+
+    format!("\n\n`/party invite {}`", usernames)
+}
+
+impl LFGMessage {
+    fn from_create(message: &MessageCreate) -> Self {
+        LFGMessage {
+            id: message.id,
+            guild_id: message.guild_id,
+            channel_id: message.channel_id,
+            author: message.author.id,
+            author_bot: message.author.bot || message.author.system.unwrap_or(false),
+            content: message.content.clone(),
+            mentions: message
+                .mentions
+                .iter()
+                .filter_map(|it| if it.bot { None } else { Some(it.id) })
+                .collect_vec(),
+        }
+    }
+
+    /// Message updates are partial, so whatever is missing is filled in from the cache.
+    /// Messages from before a restart aren't cached, their updates have to carry the rest.
+    /// Updates without content, like embed unfurls, didn't change the ping and are skipped
+    fn from_update(update: &MessageUpdate, cache: &InMemoryCache) -> Option<Self> {
+        let content = update.content.clone()?;
+        let cached = cache.message(update.id);
+
+        let (author, author_bot) = match (&update.author, &cached) {
+            (Some(author), _) => (author.id, author.bot || author.system.unwrap_or(false)),
+            (None, Some(cached)) => (
+                cached.author(),
+                cache
+                    .user(cached.author())
+                    .map(|it| it.bot || it.system.unwrap_or(false))
+                    .unwrap_or(true),
+            ),
+            (None, None) => return None,
+        };
+
+        let mentions = match (&update.mentions, &cached) {
+            (Some(mentions), _) => mentions
+                .iter()
+                .filter_map(|it| if it.bot { None } else { Some(it.id) })
+                .collect_vec(),
+            (None, Some(cached)) => cached
+                .mentions()
+                .iter()
+                .filter(|it| cache.user(**it).map(|user| !user.bot).unwrap_or(true))
+                .copied()
+                .collect_vec(),
+            (None, None) => Vec::new(),
+        };
+
+        Some(LFGMessage {
+            id: update.id,
+            guild_id: update
+                .guild_id
+                .or_else(|| cached.as_ref().and_then(|it| it.guild_id())),
+            channel_id: update.channel_id,
+            author,
+            author_bot,
+            content,
+            mentions,
+        })
+    }
+}
+
+/// Everything needed to start a session apart from the mention type
+pub struct LFGRequest {
+    pub guild: Id<GuildMarker>,
+    pub channel: Id<ChannelMarker>,
+    /// the message the ping replies to and deletes once everyone is ready
+    pub original_message: Id<MessageMarker>,
+    pub author: Id<UserMarker>,
+    pub count: Count,
+    /// non-bot users that are already playing
+    pub mentions: Vec<Id<UserMarker>>,
+    /// facade and actual roles of other mention types to tag alongside this one
+    pub extra_tags: Vec<(Id<RoleMarker>, Id<RoleMarker>)>,
+    /// set when the original message is the bot's own, which the author can't delete
+    pub cancel_button: bool,
+}
+
+/// A session a message asks for
+struct PlannedSession {
+    count: Count,
+    lfg_type: LFGType,
+    extra_tags: Vec<(Id<RoleMarker>, Id<RoleMarker>)>,
+}
+
+/// What a message pinging LFG roles turned out to ask for
+enum PingPlan {
+    /// no configured LFG role was pinged
+    NotAPing,
+    /// nothing that looks like a count, and no team size to assume
+    MissingCount,
+    /// there were counts, but none of them were valid or fit
+    NoFittingCount(LFGType),
+    Sessions(Vec<PlannedSession>),
+}
 
 pub struct LFGManager {
-    pub mention_types: Tree,
+    storage: Arc<dyn Storage>,
     pub sessions: RwLock<HashMap<Uuid, LFGSession>>,
-    pub session_uuids: RwLock<HashMap<Id<MessageMarker>, Uuid>>,
-    pub session_timeouts: RwLock<HashMap<Uuid, AbortHandle>>,
+    /// every session started by a message, one per role when roles get separate pings
+    pub session_uuids: RwLock<HashMap<Id<MessageMarker>, Vec<Uuid>>>,
+    pub expiry: ExpiryScheduler,
+}
+
+/// The session and the others started by the same message, which share participants
+fn linked_ids(sessions: &HashMap<Uuid, LFGSession>, session: &LFGSession) -> Vec<Uuid> {
+    std::iter::once(session.uuid)
+        .chain(
+            sessions
+                .values()
+                .filter(|it| it.original_message == session.original_message)
+                .map(|it| it.uuid)
+                .filter(|it| *it != session.uuid),
+        )
+        .collect()
+}
+
+/// What happens to live sessions when the bot shuts down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownPolicy {
+    /// keep them stored so they pick up where they left off on the next start
+    Persist,
+    /// end them, telling everyone the ping is closed
+    Expire,
+}
+
+impl FromStr for ShutdownPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "persist" => Ok(ShutdownPolicy::Persist),
+            "expire" => Ok(ShutdownPolicy::Expire),
+            _ => Err(anyhow!(
+                "unknown shutdown policy `{value}`, expected `persist` or `expire`"
+            )),
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -53,41 +226,133 @@ pub enum ExpiryStrategy {
     DeleteOriginal,
     ExpireMessageStale,
     ExpireMessageCancelled,
+    /// another role pinged by the same message filled up first
+    ExpireMessageFilled,
+    /// the bot is going down and was told not to keep sessions around
+    ExpireMessageShutdown,
     DoNothing,
 }
 
 impl LFGManager {
-    pub fn new(db: &Db) -> Result<Self> {
+    pub fn new(storage: Arc<dyn Storage>) -> Result<Self> {
+        let mut sessions = HashMap::new();
+        let mut session_uuids = HashMap::new();
+        for session in storage.sessions().context("reading stored sessions")? {
+            session_uuids
+                .entry(session.original_message)
+                .or_insert_with(Vec::new)
+                .push(session.uuid);
+            sessions.insert(session.uuid, session);
+        }
+
+        info!("restored {} lfg sessions", sessions.len());
+
+        // sessions whose time passed while the bot was down are due straight away
+        let expiry = ExpiryScheduler::new(sessions.values().map(|it| (it.uuid, it.expiry)));
+
         Ok(LFGManager {
-            mention_types: db.open_tree("mention_types")?,
-            sessions: RwLock::new(HashMap::new()),
-            session_uuids: RwLock::new(HashMap::new()),
-            session_timeouts: RwLock::new(HashMap::new()),
+            storage,
+            sessions: RwLock::new(sessions),
+            session_uuids: RwLock::new(session_uuids),
+            expiry,
         })
     }
 
+    /// Expires sessions as their deadlines come up, meant to be spawned once
+    pub async fn run_expiry(&self, context: Arc<ChairContext>) {
+        loop {
+            let session_id = self.expiry.next_due().await;
+
+            if let Err(cause) = self
+                .expire_session(
+                    context.clone(),
+                    ExpiryStrategy::ExpireMessageStale,
+                    session_id,
+                )
+                .await
+            {
+                warn!(?cause, %session_id, "error expiring session");
+            }
+        }
+    }
+
+    /// Checkpoints or ends every live session, meant to run once the gateway has stopped
+    /// handing out events
+    pub async fn shutdown(&self, context: Arc<ChairContext>, policy: ShutdownPolicy) -> Result<()> {
+        let sessions = self.sessions.read().await;
+        let session_ids = sessions.keys().copied().collect_vec();
+
+        if policy == ShutdownPolicy::Persist {
+            for session in sessions.values() {
+                self.store_session(session)?;
+            }
+            info!("kept {} lfg sessions for the next start", session_ids.len());
+            return Ok(());
+        }
+        drop(sessions);
+
+        for session_id in &session_ids {
+            if let Err(cause) = self
+                .expire_session(
+                    context.clone(),
+                    ExpiryStrategy::ExpireMessageShutdown,
+                    *session_id,
+                )
+                .await
+            {
+                warn!(?cause, %session_id, "error closing session");
+            }
+        }
+        info!("closed {} lfg sessions", session_ids.len());
+
+        Ok(())
+    }
+
+    fn store_session(&self, session: &LFGSession) -> Result<()> {
+        self.storage.store_session(session)
+    }
+
+    /// Returns whether this call ended the session, it may have ended already
     async fn expire_session(
         &self,
         context: Arc<ChairContext>,
         strategy: ExpiryStrategy,
         session_id: Uuid,
-    ) -> Result<()> {
-        let mut timeouts = self.session_timeouts.write().await;
-        // performs an abortion if there was a fetus
-        if let Some(abort_handle) = timeouts.remove(&session_id) {
-            abort_handle.abort()
-        }
-        drop(timeouts);
-
+    ) -> Result<bool> {
         let mut sessions = self.sessions.write().await;
         let session = match sessions.remove(&session_id) {
             Some(v) => v,
-            None => return Ok(()),
+            None => {
+                drop(sessions);
+                self.expiry.cancel(session_id).await;
+                return Ok(false);
+            }
         };
         drop(sessions);
 
+        self.end_session(context, strategy, session).await?;
+
+        Ok(true)
+    }
+
+    /// Cleans up after a session already taken out of `sessions`
+    async fn end_session(
+        &self,
+        context: Arc<ChairContext>,
+        strategy: ExpiryStrategy,
+        session: LFGSession,
+    ) -> Result<()> {
+        let session_id = session.uuid;
+        self.expiry.cancel(session_id).await;
+        self.storage.remove_session(session_id)?;
+
         let mut session_uuids = self.session_uuids.write().await;
-        session_uuids.remove(&session.original_message);
+        if let Some(session_ids) = session_uuids.get_mut(&session.original_message) {
+            session_ids.retain(|it| *it != session_id);
+            if session_ids.is_empty() {
+                session_uuids.remove(&session.original_message);
+            }
+        }
         drop(session_uuids);
 
         let reply_message = match session.reply_message {
@@ -100,64 +365,99 @@ impl LFGManager {
         }
 
         if strategy == ExpiryStrategy::DeleteOriginal {
-            let delete_message = context
-                .http
-                .delete_message(session.channel, session.original_message)
-                .reason("LFG Ping expired")
-                .context("setting audit log reason")?;
-            delete_message.await?;
+            context
+                .discord
+                .delete_message(
+                    session.channel,
+                    session.original_message,
+                    "LFG Ping expired",
+                )
+                .await?;
             return Ok(());
         }
 
-        let mut update = context
-            .http
-            .update_message(session.channel, reply_message)
-            .content(None)
-            .context("setting content to none")?
-            .components(Some(&[]))
-            .context("setting components to none")?
-            .allowed_mentions(Some(BLANK_ALLOWED_MENTIONS));
         info!("shayTA");
 
-        let embed = if strategy == ExpiryStrategy::ExpireMessageStale {
-            simple_embed(
-                0xff3030,
+        let lfg_type = self.mention_type(session.guild, session.facade_tag, session.initial_tag);
+
+        let embed = match strategy {
+            ExpiryStrategy::ExpireMessageStale => simple_embed(
+                lfg_type.expired_color,
                 "Expired ping",
-                EXPIRED_MESSAGES
+                lfg_type
+                    .expired_messages
                     .choose(&mut thread_rng())
-                    .expect("could not do trivial task"),
-            )?
-        } else {
-            simple_embed(
-                0xff3030,
+                    .map(String::as_str)
+                    .unwrap_or("The ping expired"),
+            )?,
+            ExpiryStrategy::ExpireMessageFilled => simple_embed(
+                lfg_type.expired_color,
+                "Filled elsewhere",
+                "Another role pinged by the same message filled up first",
+            )?,
+            ExpiryStrategy::ExpireMessageShutdown => simple_embed(
+                lfg_type.expired_color,
+                "Closed ping",
+                "The bot is restarting, ping again in a bit",
+            )?,
+            _ => simple_embed(
+                lfg_type.expired_color,
                 "Cancelled ping",
                 &format!(
                     "No, that wasn't a ghost... it just looks like <@{}> backed out!",
                     session.author
                 ),
-            )?
+            )?,
         };
 
-        let embeds = &[embed];
-
-        update = update.embeds(Some(embeds))?;
-        update.await?;
+        let edit = MessageEdit {
+            content: Some(None),
+            embeds: Some(vec![embed]),
+            components: Some(Vec::new()),
+            allowed_mentions: Some(blank_allowed_mentions()),
+        };
+        context
+            .discord
+            .update_message(session.channel, reply_message, edit)
+            .await?;
 
         info!("shatTB");
 
         Ok(())
     }
 
-    async fn render_message(&self, context: Arc<ChairContext>, session: LFGSession) -> Result<()> {
+    async fn render_message(&self, context: Arc<ChairContext>, session_id: Uuid) -> Result<()> {
+        // always render the latest state, and whoever takes a full session out is the one
+        // who announces it
+        let mut sessions = self.sessions.write().await;
+        let session = match sessions.get(&session_id) {
+            Some(v) => v.clone(),
+            None => return Ok(()),
+        };
+        let linked = linked_ids(&sessions, &session);
+        let completed = session.is_full();
+        if completed {
+            sessions.remove(&session_id);
+        }
+        drop(sessions);
+
+        let lfg_type = self.mention_type(session.guild, session.facade_tag, session.initial_tag);
         let numerator = session.initial_number as usize + session.participants.len();
 
-        let mut participants = format!("\n\n**Participants:**\n`•` <@{}>", session.author);
-        participants += &session
-            .participants
+        let members = std::iter::once(session.author)
+            .chain(session.participants.iter().copied())
+            .chain(session.added_participants.iter().copied())
+            .map(|it| (it, linked_username(&context, it)))
+            .collect_vec();
+
+        let mut participants = "\n\n**Participants:**".to_owned();
+        participants += &members
             .iter()
-            .chain(session.added_participants.iter())
-            .map(|it| format!("`•` <@{}>", it))
-            .join("\n");
+            .map(|(id, username)| match username {
+                Some(username) => format!("\n`•` <@{}> (`{}`)", id, username),
+                None => format!("\n`•` <@{}>", id),
+            })
+            .join("");
 
         let actual = session.added_participants.len() + session.participants.len() + 1;
 
@@ -165,58 +465,68 @@ impl LFGManager {
             participants += &format!("\n`•` **and {} other(s)...**", numerator - actual);
         }
 
-        participants += "\n\n*delete the original message to cancel*";
+        if !session.cancel_button {
+            participants += "\n\n*delete the original message to cancel*";
+        }
 
-        if session.initial_number as usize + session.participants.len()
-            >= session.required_number as usize
-        {
-            let mut mentions = format!("<@{}>", session.author);
+        if completed {
+            let mut mentions = format!("<@{}> ", session.author);
             mentions += &session
                 .participants
                 .iter()
                 .chain(session.added_participants.iter())
+                .filter(|it| !session.excluded_participants.contains(it))
                 .map(|it| format!("<@{}>", it))
                 .join(" ");
 
-            self.expire_session(
+            for session_id in linked.into_iter().skip(1) {
+                self.expire_session(
+                    context.clone(),
+                    ExpiryStrategy::ExpireMessageFilled,
+                    session_id,
+                )
+                .await?;
+            }
+
+            self.end_session(
                 context.clone(),
                 ExpiryStrategy::DeleteOriginal,
-                session.uuid,
+                session.clone(),
             )
             .await?;
 
             let embed = simple_embed(
-                0x8ae24a,
+                lfg_type.color,
                 &format!(
                     "Everyone's ready! [{}/{}]",
                     numerator, session.required_number
                 ),
-                "Good luck everyone! Make wife proud!",
+                &(lfg_type.completed_message.clone() + &party_invite(&session, &members)),
             )?;
-            let embeds = &[embed];
 
-            let allow_users_roles_mentions = &AllowedMentions {
+            let allow_users_roles_mentions = AllowedMentions {
                 replied_user: false,
                 parse: vec![MentionType::Roles, MentionType::Users],
                 roles: vec![],
                 users: vec![],
             };
 
+            let message = NewMessage {
+                content: Some(format!("||{mentions}||")),
+                embeds: vec![embed],
+                allowed_mentions: Some(allow_users_roles_mentions),
+                ..Default::default()
+            };
             context
-                .http
-                .create_message(session.channel)
-                .content(&format!("||{mentions}||"))
-                .context("invalid message body")?
-                .embeds(embeds)
-                .context("invalid embed")?
-                .allowed_mentions(Some(allow_users_roles_mentions))
+                .discord
+                .create_message(session.channel, message)
                 .await?;
 
             return Ok(());
         }
 
         let embed = simple_embed(
-            0x8ae24a,
+            lfg_type.color,
             &format!("LFG Ping [{}/{}]", numerator, session.required_number),
             &format!(
                 "<@{}> is looking for a game! (expires <t:{}:R>){}",
@@ -225,44 +535,75 @@ impl LFGManager {
                 participants
             ),
         )?;
-        let embeds = &[embed];
-
-        let component = Component::ActionRow(ActionRow {
-            components: vec![Component::Button(Button {
+        let mut buttons = vec![
+            Component::Button(Button {
                 custom_id: Some(format!("lfg-{}", session.uuid)),
                 disabled: false,
                 emoji: None,
-                label: Some("Logging on / Online!".to_owned()),
+                label: Some(lfg_type.button_label.clone()),
                 style: ButtonStyle::Success,
                 url: None,
-            })],
+            }),
+            Component::Button(Button {
+                custom_id: Some(format!("lfg-leave-{}", session.uuid)),
+                disabled: false,
+                emoji: None,
+                label: Some("Can't make it anymore".to_owned()),
+                style: ButtonStyle::Secondary,
+                url: None,
+            }),
+        ];
+
+        if session.cancel_button {
+            buttons.push(Component::Button(Button {
+                custom_id: Some(format!("lfg-cancel-{}", session.uuid)),
+                disabled: false,
+                emoji: None,
+                label: Some("Cancel ping".to_owned()),
+                style: ButtonStyle::Danger,
+                url: None,
+            }));
+        }
+
+        let component = Component::ActionRow(ActionRow {
+            components: buttons,
         });
 
-        let components = &[component];
+        let (facades, actuals): (Vec<_>, Vec<_>) =
+            std::iter::once((session.facade_tag, session.initial_tag))
+                .chain(session.extra_tags.iter().copied())
+                .map(|(facade, actual)| (format!("<@&{facade}>"), format!("<@&{actual}>")))
+                .unzip();
+
+        let content = format!(
+            "{} `{}/{}`    ||{}||",
+            facades.join(" "),
+            numerator,
+            session.required_number,
+            actuals.join(" ")
+        );
 
         let reply_id = match session.reply_message {
             None => {
-                let sent = context
-                    .http
-                    .create_message(session.channel)
-                    .reply(session.original_message)
-                    .content(&format!(
-                        "<@&{}> `{}/{}`    ||<@&{}>||",
-                        session.facade_tag, numerator, session.required_number, session.initial_tag
-                    ))
-                    .context("setting content")?
-                    .embeds(embeds)?
-                    .components(components)?
+                let message = NewMessage {
+                    reply: Some(session.original_message),
+                    content: Some(content),
+                    embeds: vec![embed],
+                    components: vec![component],
+                    allowed_mentions: None,
+                };
+                let sent_id = context
+                    .discord
+                    .create_message(session.channel, message)
                     .await?;
 
-                let sent_message = sent.model().await?;
-
                 let mut sessions = self.sessions.write().await;
                 let current_session = match sessions.get_mut(&session.uuid) {
                     Some(v) => v,
                     None => return Ok(()),
                 };
-                current_session.reply_message = Some(sent_message.id);
+                current_session.reply_message = Some(sent_id);
+                self.store_session(current_session)?;
                 drop(sessions);
 
                 return Ok(());
@@ -270,173 +611,1370 @@ impl LFGManager {
             Some(v) => v,
         };
 
+        let edit = MessageEdit {
+            content: Some(Some(content)),
+            embeds: Some(vec![embed]),
+            ..Default::default()
+        };
         context
-            .http
-            .update_message(session.channel, reply_id)
-            .embeds(Some(embeds))?
+            .discord
+            .update_message(session.channel, reply_id, edit)
             .await?;
 
         Ok(())
     }
 
-    fn message_format(&self, content: &str) -> Option<(u8, u8, u64, u64)> {
-        if content.len() < 3 {
-            return None;
+    /// Settings for the mention type with the given facade role, falling back to defaults
+    /// if it has since been removed
+    fn mention_type(
+        &self,
+        guild: Id<GuildMarker>,
+        facade: Id<RoleMarker>,
+        actual: Id<RoleMarker>,
+    ) -> LFGType {
+        match self.storage.mention_type(guild, facade) {
+            Ok(v) => v,
+            Err(cause) => {
+                warn!(?cause, "error reading mention type");
+                None
+            }
+        }
+        .unwrap_or_else(|| LFGType::new(facade, actual))
+    }
+
+    fn guild_settings(&self, guild: Id<GuildMarker>) -> Result<GuildSettings> {
+        Ok(self.storage.guild_settings(guild)?.unwrap_or_default())
+    }
+
+    /// Forgets everything about a guild the bot was removed from
+    pub async fn purge_guild(
+        &self,
+        context: Arc<ChairContext>,
+        guild: Id<GuildMarker>,
+    ) -> Result<()> {
+        let removed = self.storage.remove_guild_mention_types(guild)?;
+        self.storage.remove_guild_settings(guild)?;
+
+        let sessions = self.sessions.read().await;
+        let session_ids = sessions
+            .values()
+            .filter(|it| it.guild == guild)
+            .map(|it| it.uuid)
+            .collect_vec();
+        drop(sessions);
+
+        for session_id in session_ids {
+            self.expire_session(context.clone(), ExpiryStrategy::DoNothing, session_id)
+                .await?;
         }
 
-        let mut mention_type: Option<(u64, u64)> = None;
-        for entry in self.mention_types.iter() {
-            match entry {
-                Ok(v) => {
-                    let key = coerce_into_u64(v.0.as_ref());
-                    let value = coerce_into_u64(v.1.as_ref());
+        info!(%guild, "purged {} mention types", removed);
 
-                    let key_str = &key.to_string();
+        Ok(())
+    }
 
-                    if content.contains(key_str) {
-                        mention_type = Some((key, value));
-                        break;
-                    }
-                }
+    /// Picks the first count that fits the mention type, pinning relative counts to its
+    /// team size. Counts that don't fit are as likely to be dates as oversized pings, so
+    /// they're passed over. The team size is only assumed, if the type has one, when
+    /// nothing in the message looked like a count
+    fn pick_count(ping: &ParsedPing, lfg_type: &LFGType) -> Option<Count> {
+        if ping.counts.is_empty() && !ping.rejected_counts {
+            return lfg_type.team_size.map(|size| Count::Fraction {
+                have: 1,
+                need: size,
+            });
+        }
 
+        ping.counts
+            .iter()
+            .map(|it| it.within_team(lfg_type.team_size))
+            .find(|it| it.fits(lfg_type.max_denominator))
+    }
+
+    /// Works out which sessions a message asks for, depending on how the guild handles
+    /// pinging more than one LFG role at once
+    fn plan_sessions(&self, guild: Id<GuildMarker>, content: &str) -> PingPlan {
+        let ping = parser::parse(content);
+
+        let formats = ping
+            .roles
+            .iter()
+            .unique()
+            .filter_map(|role| match self.storage.mention_type(guild, *role) {
+                Ok(v) => v,
                 Err(cause) => {
                     warn!(?cause, "error reading mention types");
-                    continue;
+                    None
                 }
-            }
+            })
+            .map(|it| (Self::pick_count(&ping, &it), it))
+            .collect_vec();
+
+        let mut valid = formats
+            .iter()
+            .filter_map(|(count, lfg_type)| count.map(|it| (it, lfg_type.clone())))
+            .collect_vec();
+
+        if valid.is_empty() {
+            return match formats.into_iter().next() {
+                None => PingPlan::NotAPing,
+                Some(_) if ping.counts.is_empty() && !ping.rejected_counts => {
+                    PingPlan::MissingCount
+                }
+                Some((_, lfg_type)) => PingPlan::NoFittingCount(lfg_type),
+            };
         }
 
-        let mention_type = match mention_type {
-            Some(v) => v,
-            None => return None,
+        let settings = match self.guild_settings(guild) {
+            Ok(v) => v,
+            Err(cause) => {
+                warn!(?cause, %guild, "error reading guild settings");
+                GuildSettings::default()
+            }
         };
 
-        let (_, numerator, denominator) = match regex_captures!(r"(\d{1,2})\/(\d{1,2})", content) {
-            Some(v) => v,
-            None => return Some((0, 0, mention_type.0, mention_type.1)),
+        let planned = match settings.multi_role_mode {
+            MultiRoleMode::Separate => valid
+                .into_iter()
+                .map(|(count, lfg_type)| PlannedSession {
+                    count,
+                    lfg_type,
+                    extra_tags: Vec::new(),
+                })
+                .collect_vec(),
+            MultiRoleMode::Combined => {
+                let (count, lfg_type) = valid.remove(0);
+                let extra_tags = formats
+                    .iter()
+                    .map(|(_, it)| (it.facade, it.actual))
+                    .filter(|(facade, _)| *facade != lfg_type.facade)
+                    .collect_vec();
+
+                vec![PlannedSession {
+                    count,
+                    lfg_type,
+                    extra_tags,
+                }]
+            }
         };
 
-        let numerator = numerator
-            .parse::<u8>()
-            .expect("could not parse a numerator");
-
-        let denominator = denominator
-            .parse::<u8>()
-            .expect("could not parse a denominator");
-
-        Some((numerator, denominator, mention_type.0, mention_type.1))
+        PingPlan::Sessions(planned)
     }
 
     async fn create_lfg(
         &self,
         context: Arc<ChairContext>,
-        message: Box<MessageCreate>,
+        message: LFGMessage,
+        edited: bool,
     ) -> Result<()> {
         let guild_id = match message.guild_id {
             Some(v) => v,
             None => return Ok(()),
         };
 
-        if message.author.bot || message.author.system.unwrap_or(false) {
+        if message.author_bot {
             return Ok(());
         }
 
-        let (numerator, denominator, facade_tag, real_tag) =
-            match self.message_format(&message.content) {
-                Some(v) => v,
-                None => return Ok(()),
-            };
+        let embed = match self.plan_sessions(guild_id, &message.content) {
+            PingPlan::NotAPing => return Ok(()),
+            PingPlan::Sessions(planned) => {
+                for plan in planned {
+                    let request = LFGRequest {
+                        guild: guild_id,
+                        channel: message.channel_id,
+                        original_message: message.id,
+                        author: message.author,
+                        count: plan.count,
+                        mentions: message.mentions.clone(),
+                        extra_tags: plan.extra_tags,
+                        cancel_button: false,
+                    };
+
+                    self.start_session(context.clone(), request, &plan.lfg_type)
+                        .await?;
+                }
 
-        if denominator == 0 {
-            let embed = simple_embed(
+                return Ok(());
+            }
+            // the author was already told off when the message was first sent
+            _ if edited => return Ok(()),
+            PingPlan::MissingCount => simple_embed(
                 0xff3030,
-                "Use the LFG Ping", 
-                "You cannot ping LFG roles without providing an indicator as to how many are playing, i.e. `@2v2pings 2/4`. Feel free to edit your message if you want to ping, as nobody has been pinged yet.")
-                .context("what")?;
-
-            let embeds = &[embed];
-
-            context
-                .http
-                .create_message(message.channel_id)
-                .reply(message.id)
-                .embeds(embeds)
-                .context("epic embed failure")?
-                .await?;
+                "Use the LFG Ping",
+                "You cannot ping LFG roles without providing an indicator as to how many are playing, i.e. `@2v2pings 2/4` or `@2v2pings +1`. Feel free to edit your message if you want to ping, as nobody has been pinged yet.")
+                .context("what")?,
+            PingPlan::NoFittingCount(lfg_type) => simple_embed(
+                lfg_type.expired_color,
+                "No count fits",
+                &format!(
+                    "None of the counts in your message fit this LFG role, which goes up to `{}` players, i.e. `@2v2pings 2/4` or `@2v2pings +1`. Feel free to edit your message if you want to ping, as nobody has been pinged yet.",
+                    lfg_type.max_denominator
+                ),
+            )?,
+        };
 
-            return Ok(());
-        }
+        let reply = NewMessage {
+            reply: Some(message.id),
+            embeds: vec![embed],
+            ..Default::default()
+        };
+        context
+            .discord
+            .create_message(message.channel_id, reply)
+            .await
+            .context("epic embed failure")?;
 
-        let valid_mentions = message
+        Ok(())
+    }
+    /// Starts a session for a ping, shared by pinging in a message and `/lfg`.
+    /// Returns false when the ping is already full or can't fit in the mention type
+    pub async fn start_session(
+        &self,
+        context: Arc<ChairContext>,
+        request: LFGRequest,
+        lfg_type: &LFGType,
+    ) -> Result<bool> {
+        let valid_mentions = request
             .mentions
-            .iter()
-            .filter_map(|it| if it.bot { None } else { Some(it.id) })
+            .into_iter()
+            .filter(|it| *it != request.author)
+            .unique()
             .collect_vec();
 
-        let initial_numerator = (valid_mentions.len() + 1).max(numerator as usize);
+        let (initial_numerator, required) = request.count.resolve(valid_mentions.len() + 1, 0);
 
-        if initial_numerator >= denominator as usize {
-            return Ok(());
+        if initial_numerator >= required || required > lfg_type.max_denominator as usize {
+            return Ok(false);
         }
 
         let session_id = Uuid::new_v4();
-        let session = LFGSession {
+        let mut session = LFGSession {
             uuid: session_id,
-            guild: guild_id,
-            channel: message.channel_id,
-            original_message: message.id,
+            guild: request.guild,
+            channel: request.channel,
+            original_message: request.original_message,
             reply_message: None,
-            author: message.author.id,
-            facade_tag: Id::<RoleMarker>::new_checked(facade_tag)
-                .context("cannot create facade tag marker")?,
-            initial_tag: Id::<RoleMarker>::new_checked(real_tag)
-                .context("cannot create real tag marker")?,
+            author: request.author,
+            facade_tag: lfg_type.facade,
+            initial_tag: lfg_type.actual,
             participants: Vec::new(),
             added_participants: valid_mentions,
             excluded_participants: Vec::new(),
             interested_participants: Vec::new(),
             initial_number: initial_numerator as u8,
-            required_number: denominator,
-            expiry: Utc::now() + Duration::minutes(30),
+            required_number: required as u8,
+            expiry: Utc::now() + Duration::minutes(lfg_type.expiry_minutes as i64),
+            extra_tags: request.extra_tags,
+            cancel_button: request.cancel_button,
         };
 
         let mut sessions = self.sessions.write().await;
+
+        // whoever already answered a linked ping is in this one too
+        if let Some(linked) = sessions
+            .values()
+            .find(|it| it.original_message == session.original_message)
+        {
+            session.participants = linked.participants.clone();
+            session.excluded_participants = linked.excluded_participants.clone();
+            session
+                .added_participants
+                .retain(|it| !session.excluded_participants.contains(it));
+        }
+
         sessions.insert(session_id, session.clone());
+        self.store_session(&session)?;
         drop(sessions);
 
-        let context_clone = context.clone();
-        let task = tokio::spawn(async move {
-            time::sleep(time::Duration::from_secs(10)).await;
-            tokio::spawn(async move {
-                context_clone
-                    .lfg
-                    .expire_session(
-                        context_clone.clone(),
-                        ExpiryStrategy::ExpireMessageStale,
-                        session_id,
+        self.expiry.schedule(session_id, session.expiry).await;
+
+        let mut session_uuids = self.session_uuids.write().await;
+        session_uuids
+            .entry(session.original_message)
+            .or_insert_with(Vec::new)
+            .push(session_id);
+        drop(session_uuids);
+
+        self.render_message(context, session_id).await?;
+
+        Ok(true)
+    }
+
+    pub async fn join_session(
+        &self,
+        context: Arc<ChairContext>,
+        interaction: InteractionCreate,
+        session_id: Uuid,
+    ) -> Result<()> {
+        let user = interaction
+            .author_id()
+            .context("join interaction has no author")?;
+
+        let mut sessions = self.sessions.write().await;
+        let (embed, joined) = match sessions.get(&session_id) {
+            None => (
+                simple_embed(0xff3030, "Expired ping", "This ping is no longer active")?,
+                Vec::new(),
+            ),
+            Some(session) => {
+                if session.author == user {
+                    (
+                        simple_embed(0xff3030, "Nice try", "You can't join your own ping!")?,
+                        Vec::new(),
                     )
-                    .await
-            });
-        });
+                } else if session.excluded_participants.contains(&user) {
+                    (
+                        simple_embed(
+                            0xff3030,
+                            "Not this time",
+                            "You've been taken off this ping and can't rejoin it",
+                        )?,
+                        Vec::new(),
+                    )
+                } else if session.participants.contains(&user)
+                    || session.added_participants.contains(&user)
+                {
+                    (
+                        simple_embed(0xff3030, "Already in", "You're already part of this ping")?,
+                        Vec::new(),
+                    )
+                } else if session.is_full() {
+                    (
+                        simple_embed(
+                            0xff3030,
+                            "Already full",
+                            "This ping already has everyone it needs",
+                        )?,
+                        Vec::new(),
+                    )
+                } else {
+                    let author = session.author;
+                    let mut joined = Vec::new();
+                    for session_id in linked_ids(&sessions, session) {
+                        let session = match sessions.get_mut(&session_id) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        if session.author == user
+                            || session.excluded_participants.contains(&user)
+                            || session.participants.contains(&user)
+                            || session.added_participants.contains(&user)
+                            || session.is_full()
+                        {
+                            continue;
+                        }
+
+                        session.participants.push(user);
+                        self.store_session(session)?;
+                        joined.push(session_id);
+                    }
 
-        let mut timeouts = self.session_timeouts.write().await;
-        timeouts.insert(session_id, task.abort_handle());
-        drop(timeouts);
+                    (
+                        simple_embed(
+                            0x8ae24a,
+                            "You're in!",
+                            &format!("<@{}> has been told you're coming", author),
+                        )?,
+                        joined,
+                    )
+                }
+            }
+        };
+        drop(sessions);
 
-        let mut session_uuids = self.session_uuids.write().await;
-        session_uuids.insert(message.id, session_id);
+        context
+            .discord
+            .create_response(
+                interaction.id,
+                &interaction.token,
+                embed_response(embed, true),
+            )
+            .await
+            .context("responding to join")?;
+
+        for session_id in joined {
+            self.render_message(context.clone(), session_id).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn leave_session(
+        &self,
+        context: Arc<ChairContext>,
+        interaction: InteractionCreate,
+        session_id: Uuid,
+    ) -> Result<()> {
+        let user = interaction
+            .author_id()
+            .context("leave interaction has no author")?;
+
+        let mut sessions = self.sessions.write().await;
+        let (embed, left) = match sessions.get(&session_id) {
+            None => (
+                simple_embed(0xff3030, "Expired ping", "This ping is no longer active")?,
+                Vec::new(),
+            ),
+            Some(session) => {
+                if session.author == user {
+                    let how = if session.cancel_button {
+                        "Use the \"Cancel ping\" button to cancel the ping"
+                    } else {
+                        "Delete the original message to cancel the ping"
+                    };
+                    (simple_embed(0xff3030, "It's your ping", how)?, Vec::new())
+                } else if session.participants.contains(&user)
+                    || session.added_participants.contains(&user)
+                {
+                    let author = session.author;
+                    let mut left = Vec::new();
+                    for session_id in linked_ids(&sessions, session) {
+                        let session = match sessions.get_mut(&session_id) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        if session.author == user || session.excluded_participants.contains(&user) {
+                            continue;
+                        }
+
+                        session.participants.retain(|it| *it != user);
+                        // added participants were counted up front in the initial number
+                        if let Some(index) =
+                            session.added_participants.iter().position(|it| *it == user)
+                        {
+                            session.added_participants.remove(index);
+                            session.initial_number =
+                                session.initial_number.saturating_sub(1).max(1);
+                        }
+                        session.excluded_participants.push(user);
+                        self.store_session(session)?;
+                        left.push(session_id);
+                    }
+
+                    (
+                        simple_embed(
+                            0x8ae24a,
+                            "You're out",
+                            &format!("<@{}> has been told you can't make it", author),
+                        )?,
+                        left,
+                    )
+                } else {
+                    (
+                        simple_embed(0xff3030, "Not in", "You aren't part of this ping")?,
+                        Vec::new(),
+                    )
+                }
+            }
+        };
+        drop(sessions);
+
+        context
+            .discord
+            .create_response(
+                interaction.id,
+                &interaction.token,
+                embed_response(embed, true),
+            )
+            .await
+            .context("responding to leave")?;
+
+        for session_id in left {
+            self.render_message(context.clone(), session_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends the author's ping, for pings whose original message they can't delete
+    pub async fn cancel_session(
+        &self,
+        context: Arc<ChairContext>,
+        interaction: InteractionCreate,
+        session_id: Uuid,
+    ) -> Result<()> {
+        let user = interaction
+            .author_id()
+            .context("cancel interaction has no author")?;
+
+        let sessions = self.sessions.read().await;
+        let (embed, cancelled) = match sessions.get(&session_id) {
+            None => (
+                simple_embed(0xff3030, "Expired ping", "This ping is no longer active")?,
+                Vec::new(),
+            ),
+            Some(session) if session.author != user => (
+                simple_embed(
+                    0xff3030,
+                    "Not your ping",
+                    "Only the author can cancel a ping",
+                )?,
+                Vec::new(),
+            ),
+            Some(session) => (
+                simple_embed(0x8ae24a, "Cancelled", "Your ping has been cancelled")?,
+                linked_ids(&sessions, session),
+            ),
+        };
+        drop(sessions);
+
+        context
+            .discord
+            .create_response(
+                interaction.id,
+                &interaction.token,
+                embed_response(embed, true),
+            )
+            .await
+            .context("responding to cancel")?;
+
+        for session_id in cancelled {
+            self.expire_session(
+                context.clone(),
+                ExpiryStrategy::ExpireMessageCancelled,
+                session_id,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn on_message_delete(
+        &self,
+        context: Arc<ChairContext>,
+        message_ids: &[Id<MessageMarker>],
+    ) -> Result<()> {
+        let session_uuids = self.session_uuids.read().await;
+        let session_ids = message_ids
+            .iter()
+            .filter_map(|it| session_uuids.get(it))
+            .flatten()
+            .copied()
+            .collect_vec();
         drop(session_uuids);
 
-        self.render_message(context.clone(), session).await?;
+        for session_id in session_ids {
+            self.expire_session(
+                context.clone(),
+                ExpiryStrategy::ExpireMessageCancelled,
+                session_id,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Lines the sessions of an edited message back up with what it now asks for,
+    /// cancelling sessions for roles that are gone and starting ones for new roles
+    async fn update_lfg(
+        &self,
+        context: Arc<ChairContext>,
+        message: LFGMessage,
+        session_ids: Vec<Uuid>,
+    ) -> Result<()> {
+        let guild_id = match message.guild_id {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let planned = match self.plan_sessions(guild_id, &message.content) {
+            PingPlan::Sessions(v) => v,
+            _ => Vec::new(),
+        };
+
+        let sessions = self.sessions.read().await;
+        let mut existing = session_ids
+            .iter()
+            .filter_map(|it| sessions.get(it))
+            .map(|it| (it.uuid, it.facade_tag))
+            .collect_vec();
+        drop(sessions);
+
+        // sessions keep their role where they can, otherwise they take over a new one
+        let mut pairs = Vec::new();
+        let mut unpaired = Vec::new();
+        for plan in planned {
+            match existing
+                .iter()
+                .position(|(_, facade)| *facade == plan.lfg_type.facade)
+            {
+                Some(index) => pairs.push((existing.remove(index).0, plan)),
+                None => unpaired.push(plan),
+            }
+        }
+
+        let mut fresh = Vec::new();
+        for plan in unpaired {
+            if existing.is_empty() {
+                fresh.push(plan);
+            } else {
+                pairs.push((existing.remove(0).0, plan));
+            }
+        }
+
+        for (session_id, _) in existing {
+            self.expire_session(
+                context.clone(),
+                ExpiryStrategy::ExpireMessageCancelled,
+                session_id,
+            )
+            .await?;
+        }
+
+        for (session_id, plan) in pairs {
+            self.update_session(context.clone(), &message.mentions, session_id, plan)
+                .await?;
+        }
+
+        for plan in fresh {
+            let request = LFGRequest {
+                guild: guild_id,
+                channel: message.channel_id,
+                original_message: message.id,
+                author: message.author,
+                count: plan.count,
+                mentions: message.mentions.clone(),
+                extra_tags: plan.extra_tags,
+                cancel_button: false,
+            };
+
+            self.start_session(context.clone(), request, &plan.lfg_type)
+                .await?;
+        }
 
         Ok(())
     }
 
+    async fn update_session(
+        &self,
+        context: Arc<ChairContext>,
+        mentions: &[Id<UserMarker>],
+        session_id: Uuid,
+        plan: PlannedSession,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        let session = match sessions.get_mut(&session_id) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let mentions = mentions
+            .iter()
+            .copied()
+            .filter(|it| {
+                *it != session.author
+                    && !session.participants.contains(it)
+                    && !session.excluded_participants.contains(it)
+            })
+            .unique()
+            .collect_vec();
+
+        let (initial_number, required) = plan
+            .count
+            .resolve(mentions.len() + 1, session.participants.len());
+
+        if required > plan.lfg_type.max_denominator as usize {
+            drop(sessions);
+            self.expire_session(context, ExpiryStrategy::ExpireMessageCancelled, session_id)
+                .await?;
+            return Ok(());
+        }
+
+        session.facade_tag = plan.lfg_type.facade;
+        session.initial_tag = plan.lfg_type.actual;
+        session.extra_tags = plan.extra_tags;
+        session.added_participants = mentions;
+        session.initial_number = initial_number as u8;
+        session.required_number = required as u8;
+        self.store_session(session)?;
+        drop(sessions);
+
+        self.render_message(context, session_id).await
+    }
     pub async fn on_message(
         &self,
         context: Arc<ChairContext>,
         event: Box<MessageCreate>,
     ) -> Result<()> {
-        self.create_lfg(context, event).await
+        self.create_lfg(context, LFGMessage::from_create(&event), false)
+            .await
+    }
+
+    pub async fn on_message_update(
+        &self,
+        context: Arc<ChairContext>,
+        event: Box<MessageUpdate>,
+    ) -> Result<()> {
+        let message = match LFGMessage::from_update(&event, &context.cache) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        if message.author_bot {
+            return Ok(());
+        }
+
+        let session_uuids = self.session_uuids.read().await;
+        let session_ids = session_uuids.get(&message.id).cloned();
+        drop(session_uuids);
+
+        match session_ids {
+            Some(session_ids) => self.update_lfg(context, message, session_ids).await,
+            None => self.create_lfg(context, message, true).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use twilight_model::{
+        application::interaction::Interaction,
+        channel::message::{
+            component::{ActionRow, Button, ButtonStyle},
+            AllowedMentions, Component, Embed, MentionType, Message,
+        },
+        gateway::payload::incoming::{InteractionCreate, MessageCreate, MessageUpdate},
+        id::{
+            marker::{ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker},
+            Id,
+        },
+    };
+    use uuid::Uuid;
+
+    use super::{LFGManager, LFGRequest};
+    use crate::{
+        commands::processor::command_handle_interaction,
+        discord::{
+            fake::{Action, RecordingActions},
+            MessageEdit, NewMessage,
+        },
+        models::{fake, ChairContext, LFGType},
+        parser::{self, Count},
+        util::{embed_response, simple_embed},
+    };
+
+    const GUILD: Id<GuildMarker> = Id::new(1);
+    const CHANNEL: Id<ChannelMarker> = Id::new(50);
+    const ORIGINAL: Id<MessageMarker> = Id::new(300);
+    const AUTHOR: Id<UserMarker> = Id::new(100);
+    const FACADE: Id<RoleMarker> = Id::new(11);
+    const ACTUAL: Id<RoleMarker> = Id::new(12);
+
+    fn context(actions: Arc<RecordingActions>) -> Arc<ChairContext> {
+        let context = fake::context(actions, Arc::default());
+        context
+            .storage
+            .store_mention_type(GUILD, &LFGType::new(FACADE, ACTUAL))
+            .unwrap();
+        context
+    }
+
+    fn user(id: Id<UserMarker>) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "username": format!("user{id}"),
+            "discriminator": "0001",
+            "avatar": null,
+        })
+    }
+
+    fn message_create(content: &str) -> Box<MessageCreate> {
+        let message: Message = serde_json::from_value(serde_json::json!({
+            "id": ORIGINAL,
+            "channel_id": CHANNEL,
+            "guild_id": GUILD,
+            "author": user(AUTHOR),
+            "content": content,
+            "timestamp": "2023-01-01T00:00:00.000000+00:00",
+            "edited_timestamp": null,
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [],
+            "mention_roles": [FACADE],
+            "attachments": [],
+            "embeds": [],
+            "pinned": false,
+            "type": 0,
+        }))
+        .unwrap();
+        Box::new(MessageCreate(message))
+    }
+
+    fn button_click(id: u64, user_id: Id<UserMarker>, custom_id: &str) -> Box<InteractionCreate> {
+        let interaction: Interaction = serde_json::from_value(serde_json::json!({
+            "id": id.to_string(),
+            "application_id": "2",
+            "type": 3,
+            "token": "token",
+            "version": 1,
+            "guild_id": GUILD,
+            "channel_id": CHANNEL,
+            "user": user(user_id),
+            "data": {
+                "custom_id": custom_id,
+                "component_type": 2,
+            },
+        }))
+        .unwrap();
+        Box::new(InteractionCreate(interaction))
+    }
+
+    fn buttons(session_id: Uuid, label: &str) -> Vec<Component> {
+        vec![Component::ActionRow(ActionRow {
+            components: vec![
+                Component::Button(Button {
+                    custom_id: Some(format!("lfg-{session_id}")),
+                    disabled: false,
+                    emoji: None,
+                    label: Some(label.to_owned()),
+                    style: ButtonStyle::Success,
+                    url: None,
+                }),
+                Component::Button(Button {
+                    custom_id: Some(format!("lfg-leave-{session_id}")),
+                    disabled: false,
+                    emoji: None,
+                    label: Some("Can't make it anymore".to_owned()),
+                    style: ButtonStyle::Secondary,
+                    url: None,
+                }),
+            ],
+        })]
+    }
+
+    fn ping_embed(have: u8, expiry: i64, participants: &str) -> Embed {
+        simple_embed(
+            0x8ae24a,
+            &format!("LFG Ping [{have}/3]"),
+            &format!(
+                "<@{AUTHOR}> is looking for a game! (expires <t:{expiry}:R>)\n\n**Participants:**{participants}\n\n*delete the original message to cancel*"
+            ),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn counts_that_dont_fit_are_passed_over() {
+        let mut lfg_type = LFGType::new(FACADE, ACTUAL);
+        let pick = |content: &str, lfg_type: &LFGType| {
+            LFGManager::pick_count(&parser::parse(content), lfg_type)
+        };
+
+        assert_eq!(pick("<@&11> scrims on 12/25", &lfg_type), None);
+        assert_eq!(
+            pick("<@&11> scrims on 12/25, 2/4 for now", &lfg_type),
+            Some(Count::Fraction { have: 2, need: 4 })
+        );
+        assert_eq!(
+            pick("<@&11> 4/16", &lfg_type),
+            Some(Count::Fraction { have: 4, need: 16 })
+        );
+
+        lfg_type.team_size = Some(4);
+        assert_eq!(
+            pick("<@&11>", &lfg_type),
+            Some(Count::Fraction { have: 1, need: 4 })
+        );
+        // a date or a count that makes no sense is not the same as no count, so the team
+        // size isn't assumed
+        assert_eq!(pick("<@&11> on 12/25", &lfg_type), None);
+        assert_eq!(pick("<@&11> scrims on 12/25/2023", &lfg_type), None);
+        assert_eq!(pick("<@&11> 5/4", &lfg_type), None);
+    }
+
+    #[tokio::test]
+    async fn ping_fills_up_through_joins() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = context(actions.clone());
+        let lfg_type = LFGType::new(FACADE, ACTUAL);
+
+        context
+            .lfg
+            .on_message(context.clone(), message_create("<@&11> 1/3"))
+            .await
+            .unwrap();
+
+        let session = context
+            .lfg
+            .sessions
+            .read()
+            .await
+            .values()
+            .next()
+            .cloned()
+            .unwrap();
+        let expiry = session.expiry.timestamp();
+
+        assert_eq!(
+            actions.take().await,
+            [Action::Create {
+                channel: CHANNEL,
+                id: Id::new(1000),
+                message: NewMessage {
+                    reply: Some(ORIGINAL),
+                    content: Some("<@&11> `1/3`    ||<@&12>||".to_owned()),
+                    embeds: vec![ping_embed(1, expiry, "\n`•` <@100>")],
+                    components: buttons(session.uuid, &lfg_type.button_label),
+                    allowed_mentions: None,
+                },
+            }]
+        );
+
+        let join = format!("lfg-{}", session.uuid);
+        command_handle_interaction(button_click(400, Id::new(201), &join), context.clone()).await;
+
+        let joined = embed_response(
+            simple_embed(0x8ae24a, "You're in!", "<@100> has been told you're coming").unwrap(),
+            true,
+        );
+        assert_eq!(
+            actions.take().await,
+            [
+                Action::Respond {
+                    interaction: Id::new(400),
+                    response: joined.clone(),
+                },
+                Action::Update {
+                    channel: CHANNEL,
+                    message: Id::new(1000),
+                    edit: MessageEdit {
+                        content: Some(Some("<@&11> `2/3`    ||<@&12>||".to_owned())),
+                        embeds: Some(vec![ping_embed(2, expiry, "\n`•` <@100>\n`•` <@201>")]),
+                        ..Default::default()
+                    },
+                },
+            ]
+        );
+
+        // the author can't count themselves twice
+        command_handle_interaction(button_click(401, AUTHOR, &join), context.clone()).await;
+        assert_eq!(
+            actions.take().await,
+            [Action::Respond {
+                interaction: Id::new(401),
+                response: embed_response(
+                    simple_embed(0xff3030, "Nice try", "You can't join your own ping!").unwrap(),
+                    true
+                ),
+            }]
+        );
+
+        command_handle_interaction(button_click(402, Id::new(202), &join), context.clone()).await;
+
+        assert_eq!(
+            actions.take().await,
+            [
+                Action::Respond {
+                    interaction: Id::new(402),
+                    response: joined,
+                },
+                Action::Delete {
+                    channel: CHANNEL,
+                    message: ORIGINAL,
+                    reason: "LFG Ping expired".to_owned(),
+                },
+                Action::Create {
+                    channel: CHANNEL,
+                    id: Id::new(1001),
+                    message: NewMessage {
+                        content: Some("||<@100> <@201> <@202>||".to_owned()),
+                        embeds: vec![simple_embed(
+                            0x8ae24a,
+                            "Everyone's ready! [3/3]",
+                            &lfg_type.completed_message
+                        )
+                        .unwrap()],
+                        allowed_mentions: Some(AllowedMentions {
+                            replied_user: false,
+                            parse: vec![MentionType::Roles, MentionType::Users],
+                            roles: vec![],
+                            users: vec![],
+                        }),
+                        ..Default::default()
+                    },
+                },
+            ]
+        );
+
+        assert!(context.lfg.sessions.read().await.is_empty());
+        assert!(context.storage.sessions().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleting_the_ping_cancels_it() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = context(actions.clone());
+
+        context
+            .lfg
+            .on_message(context.clone(), message_create("<@&11> anyone?"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            actions.take().await,
+            [Action::Create {
+                channel: CHANNEL,
+                id: Id::new(1000),
+                message: NewMessage {
+                    reply: Some(ORIGINAL),
+                    embeds: vec![simple_embed(
+                        0xff3030,
+                        "Use the LFG Ping",
+                        "You cannot ping LFG roles without providing an indicator as to how many are playing, i.e. `@2v2pings 2/4` or `@2v2pings +1`. Feel free to edit your message if you want to ping, as nobody has been pinged yet."
+                    )
+                    .unwrap()],
+                    ..Default::default()
+                },
+            }]
+        );
+
+        context
+            .lfg
+            .on_message(context.clone(), message_create("<@&11> 2/4"))
+            .await
+            .unwrap();
+        actions.take().await;
+
+        context
+            .lfg
+            .on_message_delete(context.clone(), &[ORIGINAL])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            actions.take().await,
+            [Action::Update {
+                channel: CHANNEL,
+                message: Id::new(1001),
+                edit: MessageEdit {
+                    content: Some(None),
+                    embeds: Some(vec![simple_embed(
+                        0xff3030,
+                        "Cancelled ping",
+                        "No, that wasn't a ghost... it just looks like <@100> backed out!"
+                    )
+                    .unwrap()]),
+                    components: Some(Vec::new()),
+                    allowed_mentions: Some(AllowedMentions::default()),
+                },
+            }]
+        );
+        assert!(context.lfg.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_pings_take_no_joins_and_complete_once() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = context(actions.clone());
+
+        context
+            .lfg
+            .on_message(context.clone(), message_create("<@&11> 2/3"))
+            .await
+            .unwrap();
+        actions.take().await;
+
+        // a join that filled the ping but hasn't rendered yet
+        let mut sessions = context.lfg.sessions.write().await;
+        let session = sessions.values_mut().next().unwrap();
+        session.participants.push(Id::new(201));
+        let session_id = session.uuid;
+        drop(sessions);
+
+        let join = format!("lfg-{}", session_id);
+        command_handle_interaction(button_click(400, Id::new(202), &join), context.clone()).await;
+        assert_eq!(
+            actions.take().await,
+            [Action::Respond {
+                interaction: Id::new(400),
+                response: embed_response(
+                    simple_embed(
+                        0xff3030,
+                        "Already full",
+                        "This ping already has everyone it needs"
+                    )
+                    .unwrap(),
+                    true
+                ),
+            }]
+        );
+
+        let (first, second) = tokio::join!(
+            context.lfg.render_message(context.clone(), session_id),
+            context.lfg.render_message(context.clone(), session_id),
+        );
+        first.unwrap();
+        second.unwrap();
+
+        let actions = actions.take().await;
+        assert_eq!(actions.len(), 2);
+        assert!(matches!(actions[0], Action::Delete { .. }));
+        assert!(matches!(
+            &actions[1],
+            Action::Create { message, .. } if message.content.as_deref() == Some("||<@100> <@201>||")
+        ));
+        assert!(context.lfg.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_the_author_cancels_a_command_ping() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = context(actions.clone());
+        let lfg_type = LFGType::new(FACADE, ACTUAL);
+
+        let request = LFGRequest {
+            guild: GUILD,
+            channel: CHANNEL,
+            original_message: ORIGINAL,
+            author: AUTHOR,
+            count: Count::Fraction { have: 1, need: 3 },
+            mentions: Vec::new(),
+            extra_tags: Vec::new(),
+            cancel_button: true,
+        };
+        assert!(context
+            .lfg
+            .start_session(context.clone(), request, &lfg_type)
+            .await
+            .unwrap());
+
+        let session = context
+            .lfg
+            .sessions
+            .read()
+            .await
+            .values()
+            .next()
+            .cloned()
+            .unwrap();
+
+        let mut components = buttons(session.uuid, &lfg_type.button_label);
+        if let Component::ActionRow(row) = &mut components[0] {
+            row.components.push(Component::Button(Button {
+                custom_id: Some(format!("lfg-cancel-{}", session.uuid)),
+                disabled: false,
+                emoji: None,
+                label: Some("Cancel ping".to_owned()),
+                style: ButtonStyle::Danger,
+                url: None,
+            }));
+        }
+
+        let expiry = session.expiry.timestamp();
+        assert_eq!(
+            actions.take().await,
+            [Action::Create {
+                channel: CHANNEL,
+                id: Id::new(1000),
+                message: NewMessage {
+                    reply: Some(ORIGINAL),
+                    content: Some("<@&11> `1/3`    ||<@&12>||".to_owned()),
+                    embeds: vec![simple_embed(
+                        0x8ae24a,
+                        "LFG Ping [1/3]",
+                        &format!("<@{AUTHOR}> is looking for a game! (expires <t:{expiry}:R>)\n\n**Participants:**\n`•` <@100>")
+                    )
+                    .unwrap()],
+                    components,
+                    allowed_mentions: None,
+                },
+            }]
+        );
+
+        let cancel = format!("lfg-cancel-{}", session.uuid);
+        command_handle_interaction(button_click(400, Id::new(201), &cancel), context.clone()).await;
+        assert_eq!(
+            actions.take().await,
+            [Action::Respond {
+                interaction: Id::new(400),
+                response: embed_response(
+                    simple_embed(
+                        0xff3030,
+                        "Not your ping",
+                        "Only the author can cancel a ping"
+                    )
+                    .unwrap(),
+                    true
+                ),
+            }]
+        );
+        assert_eq!(context.lfg.sessions.read().await.len(), 1);
+
+        // leaving points the author at the button, they can't delete the bot's message
+        let leave = format!("lfg-leave-{}", session.uuid);
+        command_handle_interaction(button_click(402, AUTHOR, &leave), context.clone()).await;
+        assert_eq!(
+            actions.take().await,
+            [Action::Respond {
+                interaction: Id::new(402),
+                response: embed_response(
+                    simple_embed(
+                        0xff3030,
+                        "It's your ping",
+                        "Use the \"Cancel ping\" button to cancel the ping"
+                    )
+                    .unwrap(),
+                    true
+                ),
+            }]
+        );
+
+        command_handle_interaction(button_click(401, AUTHOR, &cancel), context.clone()).await;
+        let actions = actions.take().await;
+        assert_eq!(
+            actions[0],
+            Action::Respond {
+                interaction: Id::new(401),
+                response: embed_response(
+                    simple_embed(0x8ae24a, "Cancelled", "Your ping has been cancelled").unwrap(),
+                    true
+                ),
+            }
+        );
+        assert!(matches!(
+            &actions[1..],
+            [Action::Update { message, .. }] if *message == Id::new(1000)
+        ));
+        assert!(context.lfg.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn lfg_command_pings_under_its_own_response() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = context(actions.clone());
+
+        let interaction: Interaction = serde_json::from_value(serde_json::json!({
+            "id": "400",
+            "application_id": "2",
+            "type": 2,
+            "token": "token",
+            "version": 1,
+            "guild_id": GUILD,
+            "channel": { "id": CHANNEL, "type": 0 },
+            "channel_id": CHANNEL,
+            "user": user(AUTHOR),
+            "data": {
+                "id": "3",
+                "name": "lfg",
+                "type": 1,
+                "options": [
+                    { "name": "role", "type": 3, "value": "11" },
+                    { "name": "have", "type": 4, "value": 2 },
+                    { "name": "need", "type": 4, "value": 4 },
+                    { "name": "teammate1", "type": 6, "value": "201" },
+                    { "name": "teammate2", "type": 6, "value": "201" },
+                ],
+            },
+        }))
+        .unwrap();
+        command_handle_interaction(Box::new(InteractionCreate(interaction)), context.clone()).await;
+
+        let actions = actions.take().await;
+        assert_eq!(actions.len(), 2, "{actions:#?}");
+        assert!(matches!(
+            &actions[0],
+            Action::Respond { interaction, response }
+                if *interaction == Id::new(400)
+                    && response.data.as_ref().and_then(|it| it.content.as_deref())
+                        == Some("<@100> is looking for a game!")
+        ));
+
+        // the response fetched for the ping is what it replies to
+        let session = context
+            .lfg
+            .sessions
+            .read()
+            .await
+            .values()
+            .next()
+            .cloned()
+            .unwrap();
+        assert_eq!(session.original_message, Id::new(1000));
+        assert_eq!(session.added_participants, [Id::new(201)]);
+        assert!(session.cancel_button);
+        assert!(matches!(
+            &actions[1],
+            Action::Create { id, message, .. }
+                if *id == Id::new(1001)
+                    && message.reply == Some(Id::new(1000))
+                    && message.content.as_deref() == Some("<@&11> `2/4`    ||<@&12>||")
+        ));
+    }
+
+    #[tokio::test]
+    async fn uncached_pings_can_still_be_edited() {
+        let actions = Arc::new(RecordingActions::default());
+        let context = context(actions.clone());
+
+        // nothing is cached in these tests, just like a ping from before a restart
+        context
+            .lfg
+            .on_message(context.clone(), message_create("<@&11> 1/3"))
+            .await
+            .unwrap();
+        actions.take().await;
+
+        let update: MessageUpdate = serde_json::from_value(serde_json::json!({
+            "id": ORIGINAL,
+            "channel_id": CHANNEL,
+            "guild_id": GUILD,
+            "author": user(AUTHOR),
+            "content": "<@&11> 2/3",
+            "mentions": [],
+        }))
+        .unwrap();
+        context
+            .lfg
+            .on_message_update(context.clone(), Box::new(update))
+            .await
+            .unwrap();
+
+        let recorded = actions.take().await;
+        assert!(
+            matches!(
+                recorded.as_slice(),
+                [Action::Update { message, edit, .. }]
+                    if *message == Id::new(1000)
+                        && edit.content == Some(Some("<@&11> `2/3`    ||<@&12>||".to_owned()))
+            ),
+            "{recorded:#?}"
+        );
+
+        // without the cache or the update saying who wrote it, there's nothing to go on
+        let update: MessageUpdate = serde_json::from_value(serde_json::json!({
+            "id": ORIGINAL,
+            "channel_id": CHANNEL,
+            "content": "<@&11> 1/3",
+        }))
+        .unwrap();
+        context
+            .lfg
+            .on_message_update(context.clone(), Box::new(update))
+            .await
+            .unwrap();
+        assert!(actions.take().await.is_empty());
+
+        // an embed unfurl leaves the ping as it is
+        let update: MessageUpdate = serde_json::from_value(serde_json::json!({
+            "id": ORIGINAL,
+            "channel_id": CHANNEL,
+            "author": user(AUTHOR),
+            "embeds": [],
+        }))
+        .unwrap();
+        context
+            .lfg
+            .on_message_update(context.clone(), Box::new(update))
+            .await
+            .unwrap();
+        assert!(actions.take().await.is_empty());
     }
 }
@@ -1,79 +1,188 @@
 mod commands;
 mod config;
+mod discord;
+mod expiry;
 mod lfg;
 mod models;
+mod parser;
+mod profile;
+mod schema;
+mod storage;
+mod users;
 mod util;
 
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use commands::processor::command_handle_interaction;
+use futures_util::StreamExt;
+use tokio::task::JoinSet;
 use tracing::{error, info, warn};
-use twilight_cache_inmemory::{InMemoryCache, ResourceType};
-use twilight_gateway::{Event, Intents, Shard, ShardId};
+use twilight_cache_inmemory::InMemoryCache;
+use twilight_gateway::{
+    stream::{self, ShardEventStream},
+    CloseFrame, Config, Event,
+};
 
 use crate::{
-    commands::processor::register_commands, config::ChairConfig, lfg::LFGManager,
+    commands::processor::{on_guild_create, register_commands},
+    config::{ChairConfig, LogFormat},
+    discord::{DiscordActions, HttpActions},
+    lfg::LFGManager,
     models::ChairContext,
+    profile::{HttpProfileResolver, ProfileResolver},
+    schema::MigrationOptions,
+    storage::{SledStorage, Storage},
+    users::UserManager,
 };
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    let loaded_env = dotenvy::dotenv().is_ok();
+
+    let config = match ChairConfig::load() {
+        Ok(v) => v,
+        Err(cause) => {
+            tracing_subscriber::fmt::init();
+            error!("{cause:#}");
+            return Ok(());
+        }
+    };
+
+    let subscriber = tracing_subscriber::fmt();
+    match config.log_format {
+        LogFormat::Full => subscriber.init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Pretty => subscriber.pretty().init(),
+    }
+
     color_eyre::install().expect("unable to setup panic logging");
     info!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-    match dotenvy::dotenv() {
-        Ok(_) => {}
-        Err(_) => info!("could not load .env, skipping...."),
+    if !loaded_env {
+        info!("could not load .env, skipping....");
+    }
+
+    let shutdown_policy = config.shutdown_policy;
+    let shutdown_timeout = config.shutdown_timeout;
+    let registration_mode = config.registration_mode;
+    let features = config.features;
+
+    let db = sled::open(&config.database_path)
+        .with_context(|| format!("opening {}", config.database_path.display()))?;
+
+    let migration_options = MigrationOptions {
+        legacy_guild: config.legacy_guild_id,
     };
+    schema::migrate(&db, &migration_options).context("migrating database")?;
+    let storage: Arc<dyn Storage> = Arc::new(SledStorage::new(db).context("opening storage")?);
 
-    let config = match ChairConfig::try_load_from_env() {
-        Ok(v) => v,
-        Err(_) => {
-            error!("environment variables not found!");
+    let token = config.bot_token.clone();
+    let intents = config.intents();
+    let resource_types = config.resource_types();
+    let config = Arc::new(config);
+
+    let http = Arc::new(twilight_http::Client::new(token.clone()));
+
+    let mut shards =
+        stream::create_recommended(&http, Config::new(token, intents), |_, builder| {
+            builder.build()
+        })
+        .await
+        .context("creating shards")?
+        .collect::<Vec<_>>();
+
+    let first_shard = match shards.first() {
+        Some(v) => (v.id(), v.latency().clone()),
+        None => {
+            error!("discord recommended no shards!");
             return Ok(());
         }
     };
 
-    let db = sled::open("chair.sled")?;
-    /*let test = db.open_tree("mention_types")?;
-    test.insert(
-        (1069644995780423731_u64).to_be_bytes(),
-        &(1069645017414631474_u64).to_be_bytes(),
-    )?;*/
-
-    let token = config.bot_token;
-    let intents = Intents::GUILDS | Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT;
-
-    let mut shard = Shard::new(ShardId::ONE, token.clone(), intents);
-    let http = Arc::new(twilight_http::Client::new(token));
+    info!("starting {} shards", shards.len());
 
     let application = http.current_user_application().await?.model().await?;
     let application_id = application.id;
 
     info!("logged in as {} ({})", application.name, application_id);
 
-    register_commands(&http, &application)
+    register_commands(&http, &application, registration_mode, features)
         .await
         .context("registering commands")?;
 
     let cache = Arc::new(
         InMemoryCache::builder()
-            .resource_types(ResourceType::MESSAGE)
+            .resource_types(resource_types)
             .build(),
     );
 
-    let lfg_manager = Arc::new(LFGManager::new(&db).context("creating lfg")?);
+    let discord: Arc<dyn DiscordActions> = Arc::new(HttpActions::new(http.clone(), application_id));
+    let lfg_manager = Arc::new(LFGManager::new(storage.clone()).context("creating lfg")?);
+    let user_manager = Arc::new(UserManager::new(storage.clone()));
+    let profiles: Arc<dyn ProfileResolver> = Arc::new(HttpProfileResolver::new(
+        config.profile_by_username_url.clone(),
+        config.profile_by_uuid_url.clone(),
+    )?);
+
+    let refresh_users = user_manager.clone();
+    let refresh_profiles = profiles.clone();
+    let refresh_task = tokio::spawn(async move {
+        if features.links && features.link_refresh {
+            refresh_users.run_link_refresh(refresh_profiles).await
+        }
+    });
+
+    let background_context = Arc::new(ChairContext {
+        http: http.clone(),
+        discord: discord.clone(),
+        application_id,
+        cache: cache.clone(),
+        shard: first_shard.0,
+        latency: first_shard.1,
+        config: config.clone(),
+        storage: storage.clone(),
+        lfg: lfg_manager.clone(),
+        users: user_manager.clone(),
+        profiles: profiles.clone(),
+    });
+    let expiry_context = background_context.clone();
+    let expiry_task =
+        tokio::spawn(async move { expiry_context.lfg.run_expiry(expiry_context.clone()).await });
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let mut handlers = JoinSet::new();
+
+    // every shard feeds into the same handler, the stream has to go before shards close
+    let mut events = ShardEventStream::new(shards.iter_mut());
 
     loop {
-        let event = match shard.next_event().await {
+        let (shard, event) = tokio::select! {
+            _ = &mut shutdown => {
+                info!("shutting down");
+                break;
+            }
+            Some(result) = handlers.join_next(), if !handlers.is_empty() => {
+                if let Err(cause) = result {
+                    warn!(?cause, "event handler panicked");
+                }
+                continue;
+            }
+            next = events.next() => match next {
+                Some(v) => v,
+                None => break,
+            },
+        };
+
+        let event = match event {
             Ok(v) => v,
             Err(cause) => {
-                warn!(?cause, "error receiving event");
+                warn!(?cause, shard = %shard.id(), "error receiving event");
 
                 if cause.is_fatal() {
-                    error!("fatal error!");
+                    error!(shard = %shard.id(), "fatal error!");
                     break;
                 }
 
@@ -85,30 +194,125 @@ async fn main() -> Result<()> {
 
         let context = ChairContext {
             http: http.clone(),
+            discord: discord.clone(),
             application_id,
             cache: cache.clone(),
+            shard: shard.id(),
             latency: shard.latency().clone(),
+            config: config.clone(),
+            storage: storage.clone(),
             lfg: lfg_manager.clone(),
+            users: user_manager.clone(),
+            profiles: profiles.clone(),
         };
 
-        tokio::spawn(async move {
+        handlers.spawn(async move {
             if let Err(cause) = handle_event(event, context).await {
                 warn!(?cause, "error in handling event")
             }
         });
     }
 
+    drop(events);
+
+    info!("waiting on {} event handlers", handlers.len());
+    let drained = tokio::time::timeout(shutdown_timeout, async {
+        while let Some(result) = handlers.join_next().await {
+            if let Err(cause) = result {
+                warn!(?cause, "event handler panicked");
+            }
+        }
+    })
+    .await;
+
+    if drained.is_err() {
+        warn!("aborting {} event handlers still running", handlers.len());
+        handlers.shutdown().await;
+    }
+
+    // nothing should expire sessions behind the shutdown policy's back
+    refresh_task.abort();
+    expiry_task.abort();
+
+    if let Err(cause) = lfg_manager
+        .shutdown(background_context, shutdown_policy)
+        .await
+    {
+        warn!(?cause, "error shutting down lfg sessions");
+    }
+
+    storage.flush()?;
+
+    for shard in &mut shards {
+        if let Err(cause) = shard.close(CloseFrame::NORMAL).await {
+            warn!(?cause, shard = %shard.id(), "error closing shard");
+        }
+    }
+
+    info!("goodbye");
+
     Ok(())
 }
 
+/// Resolves once the process is asked to stop with ctrl-c or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(cause) = tokio::signal::ctrl_c().await {
+            warn!(?cause, "unable to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(cause) => {
+                warn!(?cause, "unable to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
 async fn handle_event(event: Event, context: ChairContext) -> Result<()> {
     let context = Arc::new(context);
+    let lfg = context.config.features.lfg;
     match event {
-        Event::MessageCreate(msg) => {
+        Event::MessageCreate(msg) if lfg => {
             context.lfg.on_message(context.clone(), msg).await?;
         }
-        Event::MessageUpdate(msg) => {
-            info!("sup");
+        Event::MessageDelete(msg) if lfg => {
+            context
+                .lfg
+                .on_message_delete(context.clone(), &[msg.id])
+                .await?;
+        }
+        Event::MessageDeleteBulk(msgs) if lfg => {
+            context
+                .lfg
+                .on_message_delete(context.clone(), &msgs.ids)
+                .await?;
+        }
+        Event::MessageUpdate(msg) if lfg => {
+            context.lfg.on_message_update(context.clone(), msg).await?;
+        }
+        Event::GuildCreate(guild) => {
+            on_guild_create(context.clone(), guild.id).await?;
+        }
+        // unavailable means an outage rather than the bot being removed
+        Event::GuildDelete(guild) if !guild.unavailable => {
+            context.lfg.purge_guild(context.clone(), guild.id).await?;
         }
         Event::InteractionCreate(interaction) => {
             command_handle_interaction(interaction.clone(), context.clone()).await;
@@ -3,8 +3,9 @@ use std::sync::Arc;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use twilight_cache_inmemory::InMemoryCache;
-use twilight_gateway::Latency;
+use twilight_gateway::{Latency, ShardId};
 use twilight_http::client::InteractionClient;
+use twilight_interactions::command::{CommandOption, CreateOption};
 use twilight_model::id::{
     marker::{
         ApplicationMarker, ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker,
@@ -13,22 +14,77 @@ use twilight_model::id::{
 };
 use uuid::Uuid;
 
-use crate::lfg::LFGManager;
+use crate::{
+    config::ChairConfig, discord::DiscordActions, lfg::LFGManager, profile::ProfileResolver,
+    storage::Storage, users::UserManager,
+};
 
 pub struct ChairContext {
     pub http: Arc<twilight_http::Client>,
+    /// what LFG does to Discord goes through here rather than `http`
+    pub discord: Arc<dyn DiscordActions>,
     pub application_id: Id<ApplicationMarker>,
     pub cache: Arc<InMemoryCache>,
+    /// the shard the event came in on, background work uses the first shard
+    pub shard: ShardId,
+    /// latency of that shard
     pub latency: Latency,
+    pub config: Arc<ChairConfig>,
+    pub storage: Arc<dyn Storage>,
     pub lfg: Arc<LFGManager>,
+    pub users: Arc<UserManager>,
+    pub profiles: Arc<dyn ProfileResolver>,
 }
 
 impl ChairContext {
-    pub fn interaction_client(&self) -> InteractionClient {
+    pub fn interaction_client(&self) -> InteractionClient<'_> {
         self.http.interaction(self.application_id)
     }
 }
 
+#[cfg(test)]
+pub mod fake {
+    use std::sync::Arc;
+
+    use twilight_cache_inmemory::InMemoryCache;
+    use twilight_gateway::{Shard, ShardId};
+    use twilight_model::id::Id;
+
+    use super::ChairContext;
+    use crate::{
+        config::ChairConfig,
+        discord::fake::RecordingActions,
+        lfg::LFGManager,
+        profile::fake::FakeProfileResolver,
+        storage::{memory::MemoryStorage, Storage},
+        users::UserManager,
+    };
+
+    /// A context with nothing stored that records what it does to Discord
+    pub fn context(
+        actions: Arc<RecordingActions>,
+        profiles: Arc<FakeProfileResolver>,
+    ) -> Arc<ChairContext> {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let config = ChairConfig::from_layers(r#"bot_token = "token""#, &|_| None).unwrap();
+        let shard = Shard::new(ShardId::ONE, "token".to_owned(), config.intents());
+
+        Arc::new(ChairContext {
+            http: Arc::new(twilight_http::Client::new("token".to_owned())),
+            discord: actions,
+            application_id: Id::new(2),
+            cache: Arc::new(InMemoryCache::new()),
+            shard: shard.id(),
+            latency: shard.latency().clone(),
+            config: Arc::new(config),
+            storage: storage.clone(),
+            lfg: Arc::new(LFGManager::new(storage.clone()).unwrap()),
+            users: Arc::new(UserManager::new(storage)),
+            profiles,
+        })
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct LFGSession {
     pub uuid: Uuid,
@@ -46,6 +102,83 @@ pub struct LFGSession {
     pub initial_number: u8,
     pub required_number: u8,
     pub expiry: DateTime<Utc>,
+    /// facade and actual roles of the other mention types a combined ping tags
+    #[serde(default)]
+    pub extra_tags: Vec<(Id<RoleMarker>, Id<RoleMarker>)>,
+    /// the author can't delete the original message, so they get a button to cancel
+    #[serde(default)]
+    pub cancel_button: bool,
+}
+
+impl LFGSession {
+    /// Whether everyone needed has answered
+    pub fn is_full(&self) -> bool {
+        self.initial_number as usize + self.participants.len() >= self.required_number as usize
+    }
+}
+
+const DEFAULT_EXPIRED_MESSAGES: [&str; 4] = [
+    "Shoot. We left it out too long, and the ping expired",
+    "Arena is dead and this unplayed ping proves it",
+    "Maybe the ping would fill up if wife came back",
+    "*Surely* next ping will fill up right?",
+];
+
+/// Settings for a single LFG mention type, stored under its facade role
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LFGType {
+    pub facade: Id<RoleMarker>,
+    pub actual: Id<RoleMarker>,
+    pub expiry_minutes: u32,
+    pub max_denominator: u8,
+    pub color: u32,
+    pub expired_color: u32,
+    pub expired_messages: Vec<String>,
+    pub completed_message: String,
+    pub button_label: String,
+    /// Players in a full team, used when a ping only says how many more are needed
+    #[serde(default)]
+    pub team_size: Option<u8>,
+}
+
+impl LFGType {
+    pub fn new(facade: Id<RoleMarker>, actual: Id<RoleMarker>) -> Self {
+        LFGType {
+            facade,
+            actual,
+            expiry_minutes: 30,
+            max_denominator: 16,
+            color: 0x8ae24a,
+            expired_color: 0xff3030,
+            expired_messages: DEFAULT_EXPIRED_MESSAGES
+                .iter()
+                .map(|it| it.to_string())
+                .collect(),
+            completed_message: "Good luck everyone! Make wife proud!".to_owned(),
+            button_label: "Logging on / Online!".to_owned(),
+            team_size: None,
+        }
+    }
+}
+
+/// What to do with a message that pings more than one LFG role
+#[derive(
+    Deserialize, Serialize, CommandOption, CreateOption, Debug, Clone, Copy, Default, PartialEq,
+)]
+pub enum MultiRoleMode {
+    /// one session that tags every role, counted against the first
+    #[default]
+    #[option(name = "One ping tagging every role", value = "combined")]
+    Combined,
+    /// a session per role, joining or leaving one joins or leaves them all
+    #[option(name = "A ping per role", value = "separate")]
+    Separate,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct GuildSettings {
+    #[serde(default)]
+    pub multi_role_mode: MultiRoleMode,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -58,6 +191,20 @@ pub struct ChairmanUser {
     pub administrator: bool,
 }
 
+impl ChairmanUser {
+    pub fn new(id: Id<UserMarker>) -> Self {
+        let now = Utc::now();
+        ChairmanUser {
+            id,
+            main_link: None,
+            linked_uuids: None,
+            created: now,
+            updated: now,
+            administrator: false,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ChairmanLink {
     pub uuid: Uuid,
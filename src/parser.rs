@@ -0,0 +1,344 @@
+use twilight_model::id::{marker::RoleMarker, Id};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Role(Id<RoleMarker>),
+    /// any other `<...>` markup, like user or channel mentions, emojis and timestamps
+    Markup,
+    Number(u8),
+    Word(&'a str),
+    Slash,
+    Plus,
+    /// punctuation that separates the tokens around it
+    Other,
+}
+
+/// How many people a ping is asking for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Count {
+    /// `2/4` or `2 of 4`
+    Fraction { have: u8, need: u8 },
+    /// `+2` or `need 2`, relative to whoever is already playing
+    More(u8),
+}
+
+impl Count {
+    /// Resolves into the initial and required number of players. `present` is the author
+    /// and anyone mentioned with them, `joined` is anyone that joined the ping since
+    pub fn resolve(self, present: usize, joined: usize) -> (usize, usize) {
+        match self {
+            Count::Fraction { have, need } => (present.max(have as usize), need as usize),
+            Count::More(more) => (present, present + joined + more as usize),
+        }
+    }
+
+    /// Pins relative counts to a team size, `+1` in a team of 4 is the same as `3/4`
+    pub fn within_team(self, team_size: Option<u8>) -> Count {
+        match (self, team_size) {
+            (Count::More(more), Some(size)) if more < size => Count::Fraction {
+                have: size - more,
+                need: size,
+            },
+            _ => self,
+        }
+    }
+
+    /// Whether the count could possibly fit in `max` players
+    pub fn fits(self, max: u8) -> bool {
+        match self {
+            Count::Fraction { need, .. } => need <= max,
+            Count::More(more) => more < max,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedPing {
+    /// every role mentioned, in order
+    pub roles: Vec<Id<RoleMarker>>,
+    /// every valid count, in order
+    pub counts: Vec<Count>,
+    /// something looked like a count but was thrown out, like `5/4` or a date
+    pub rejected_counts: bool,
+}
+
+fn parse_markup(markup: &str) -> Token<'_> {
+    markup
+        .strip_prefix("<@&")
+        .and_then(|it| it.strip_suffix('>'))
+        .and_then(|it| it.parse::<u64>().ok())
+        .and_then(Id::new_checked)
+        .map(Token::Role)
+        .unwrap_or(Token::Markup)
+}
+
+fn tokenize(content: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = content;
+
+    while let Some(next) = rest.chars().next() {
+        if next.is_whitespace() {
+            rest = &rest[next.len_utf8()..];
+            continue;
+        }
+
+        if next == '<' {
+            if let Some(end) = rest.find('>') {
+                let markup = &rest[..=end];
+                // markup never spans whitespace, otherwise it's just a less-than sign
+                if !markup.contains(char::is_whitespace) {
+                    tokens.push(parse_markup(markup));
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        if next.is_alphanumeric() {
+            let end = rest
+                .find(|it: char| !it.is_alphanumeric())
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+
+            let token = if word.chars().all(|it| it.is_ascii_digit()) {
+                match word.parse::<u8>() {
+                    Ok(v) => Token::Number(v),
+                    Err(_) => Token::Other,
+                }
+            } else {
+                Token::Word(word)
+            };
+
+            tokens.push(token);
+            rest = &rest[end..];
+            continue;
+        }
+
+        tokens.push(match next {
+            '/' => Token::Slash,
+            '+' => Token::Plus,
+            _ => Token::Other,
+        });
+        rest = &rest[next.len_utf8()..];
+    }
+
+    tokens
+}
+
+fn fraction(have: u8, need: u8) -> Option<Count> {
+    if need == 0 || have > need {
+        return None;
+    }
+
+    Some(Count::Fraction { have, need })
+}
+
+fn more(more: u8) -> Option<Count> {
+    if more == 0 {
+        return None;
+    }
+
+    Some(Count::More(more))
+}
+
+pub fn parse(content: &str) -> ParsedPing {
+    let tokens = tokenize(content);
+    let mut ping = ParsedPing::default();
+
+    for (index, token) in tokens.iter().enumerate() {
+        if let Token::Role(id) = token {
+            ping.roles.push(*id);
+        }
+
+        let previous = index.checked_sub(1).and_then(|it| tokens.get(it));
+        let window = (
+            token,
+            tokens.get(index + 1),
+            tokens.get(index + 2),
+            tokens.get(index + 3),
+        );
+
+        // the outer `None` is for tokens that don't look like a count at all
+        let count = match window {
+            // dates like 12/25/2023 or 2023/12/25 aren't fractions
+            (Token::Number(_), Some(Token::Slash), Some(Token::Number(_)), Some(Token::Slash)) => {
+                Some(None)
+            }
+            (Token::Number(_), Some(Token::Slash), ..) if previous == Some(&Token::Slash) => {
+                Some(None)
+            }
+            (Token::Number(have), Some(Token::Slash), Some(Token::Number(need)), _) => {
+                Some(fraction(*have, *need))
+            }
+            (Token::Number(have), Some(Token::Word(word)), Some(Token::Number(need)), _)
+                if word.eq_ignore_ascii_case("of") =>
+            {
+                Some(fraction(*have, *need))
+            }
+            (Token::Plus, Some(Token::Number(count)), ..) => Some(more(*count)),
+            (Token::Word(word), Some(Token::Number(count)), ..)
+                if word.eq_ignore_ascii_case("need") =>
+            {
+                Some(more(*count))
+            }
+            _ => None,
+        };
+
+        match count {
+            Some(Some(count)) => ping.counts.push(count),
+            Some(None) => ping.rejected_counts = true,
+            None => {}
+        }
+    }
+
+    ping
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::id::Id;
+
+    use super::{parse, Count};
+
+    const ROLE: u64 = 1069644995780423731;
+
+    #[test]
+    fn roles() {
+        let cases: &[(&str, &[u64])] = &[
+            ("<@&1069644995780423731> 2/4", &[ROLE]),
+            ("<@&123> <@&456>", &[123, 456]),
+            // ids are only read out of role mentions
+            ("1069644995780423731 2/4", &[]),
+            ("<@1069644995780423731> 2/4", &[]),
+            ("<#1069644995780423731> 2/4", &[]),
+            ("<@&91234> 2/4", &[91234]),
+            ("<@&0> 2/4", &[]),
+            ("<@& 123>", &[]),
+        ];
+
+        for (content, expected) in cases {
+            let roles = parse(content).roles;
+            let expected = expected.iter().map(|it| Id::new(*it)).collect::<Vec<_>>();
+            assert_eq!(roles, expected, "roles of {content:?}");
+        }
+    }
+
+    #[test]
+    fn counts() {
+        use Count::*;
+
+        let cases: &[(&str, &[Count])] = &[
+            ("<@&1> 2/4", &[Fraction { have: 2, need: 4 }]),
+            ("<@&1> 2 / 4", &[Fraction { have: 2, need: 4 }]),
+            ("<@&1> 2 of 4", &[Fraction { have: 2, need: 4 }]),
+            ("<@&1> 2 OF 4", &[Fraction { have: 2, need: 4 }]),
+            ("<@&1> 4/4", &[Fraction { have: 4, need: 4 }]),
+            ("<@&1> +2", &[More(2)]),
+            ("<@&1> + 2", &[More(2)]),
+            ("<@&1> need 2", &[More(2)]),
+            ("<@&1> Need 1", &[More(1)]),
+            // n > m and zero denominators
+            ("<@&1> 5/4", &[]),
+            ("<@&1> 0/0", &[]),
+            ("<@&1> 1/0", &[]),
+            ("<@&1> 3 of 2", &[]),
+            ("<@&1> +0", &[]),
+            ("<@&1> need 0", &[]),
+            // dates
+            ("<@&1> on 12/25/2023", &[]),
+            ("<@&1> on 2023/12/25", &[]),
+            // a bare month and day reads as a fraction, picking a count passes it over
+            // unless the mention type could actually take that many players
+            ("<@&1> 12/25", &[Fraction { have: 12, need: 25 }]),
+            // numbers hidden in markup and words
+            ("<@&12> <@34> <t:1690000000:R>", &[]),
+            ("<@&1> 2v2", &[]),
+            ("<@&1> 1v1/2", &[]),
+            ("<@&1> 2, 4", &[]),
+            ("<@&1> 1/999", &[]),
+            ("<@&1> 300/400", &[]),
+            ("<@&1>", &[]),
+            ("", &[]),
+            // every count is kept in order
+            (
+                "<@&1> 1/2 or 2 of 4",
+                &[Fraction { have: 1, need: 2 }, Fraction { have: 2, need: 4 }],
+            ),
+            ("<@&1>2/4", &[Fraction { have: 2, need: 4 }]),
+            ("<@&1> (2/4)", &[Fraction { have: 2, need: 4 }]),
+            ("<@&1> 1/3 <@2>", &[Fraction { have: 1, need: 3 }]),
+            (
+                "<@&1> need 2 for 3/4",
+                &[More(2), Fraction { have: 3, need: 4 }],
+            ),
+            ("<@&1> ２/４", &[]),
+        ];
+
+        for (content, expected) in cases {
+            assert_eq!(parse(content).counts, *expected, "counts of {content:?}");
+        }
+    }
+
+    #[test]
+    fn rejected_counts() {
+        let cases = [
+            ("<@&1> 2/4", false),
+            ("<@&1> anyone?", false),
+            ("<@&1> 2v2", false),
+            ("<@&1> 5/4", true),
+            ("<@&1> 2/0", true),
+            ("<@&1> need 0", true),
+            ("<@&1> on 12/25/2023", true),
+            // kept even when another count was fine
+            ("<@&1> 2/4 on 12/25/2023", true),
+        ];
+
+        for (content, expected) in cases {
+            assert_eq!(
+                parse(content).rejected_counts,
+                expected,
+                "rejected counts of {content:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let cases = [
+            (Count::Fraction { have: 2, need: 4 }, 1, 0, (2, 4)),
+            (Count::Fraction { have: 1, need: 4 }, 3, 0, (3, 4)),
+            (Count::More(2), 1, 0, (1, 3)),
+            (Count::More(1), 2, 1, (2, 4)),
+        ];
+
+        for (count, present, joined, expected) in cases {
+            assert_eq!(count.resolve(present, joined), expected, "{count:?}");
+        }
+    }
+
+    #[test]
+    fn within_team() {
+        use Count::*;
+
+        let cases = [
+            (More(1), Some(4), Fraction { have: 3, need: 4 }),
+            (More(3), Some(4), Fraction { have: 1, need: 4 }),
+            // a team can't be missing everyone
+            (More(4), Some(4), More(4)),
+            (More(2), None, More(2)),
+            (
+                Fraction { have: 1, need: 2 },
+                Some(4),
+                Fraction { have: 1, need: 2 },
+            ),
+        ];
+
+        for (count, team_size, expected) in cases {
+            assert_eq!(
+                count.within_team(team_size),
+                expected,
+                "{count:?} in {team_size:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct GameProfile {
+    #[serde(rename = "id")]
+    pub uuid: Uuid,
+    pub name: String,
+}
+
+/// Looks up game accounts so links always carry a name the game agrees with
+#[async_trait]
+pub trait ProfileResolver: Send + Sync {
+    async fn by_username(&self, username: &str) -> Result<Option<GameProfile>>;
+    async fn by_uuid(&self, uuid: Uuid) -> Result<Option<GameProfile>>;
+}
+
+/// Resolves profiles against a Mojang-style API, `{}` in the urls is replaced by the
+/// username or the hyphenless UUID
+pub struct HttpProfileResolver {
+    client: reqwest::Client,
+    by_username_url: String,
+    by_uuid_url: String,
+}
+
+impl HttpProfileResolver {
+    pub fn new(by_username_url: String, by_uuid_url: String) -> Result<Self> {
+        // a hung lookup would hold up the command or the link refresh forever
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("building profile client")?;
+
+        Ok(HttpProfileResolver {
+            client,
+            by_username_url,
+            by_uuid_url,
+        })
+    }
+
+    async fn fetch(&self, url: String) -> Result<Option<GameProfile>> {
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("requesting profile {url}"))?;
+
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::NO_CONTENT
+        ) {
+            return Ok(None);
+        }
+
+        let profile = response
+            .error_for_status()
+            .with_context(|| format!("requesting profile {url}"))?
+            .json()
+            .await
+            .context("deserializing profile")?;
+        Ok(Some(profile))
+    }
+}
+
+#[async_trait]
+impl ProfileResolver for HttpProfileResolver {
+    async fn by_username(&self, username: &str) -> Result<Option<GameProfile>> {
+        // usernames are only ever letters, digits and underscores
+        if username.is_empty()
+            || !username
+                .chars()
+                .all(|it| it.is_ascii_alphanumeric() || it == '_')
+        {
+            return Ok(None);
+        }
+
+        self.fetch(self.by_username_url.replace("{}", username))
+            .await
+    }
+
+    async fn by_uuid(&self, uuid: Uuid) -> Result<Option<GameProfile>> {
+        self.fetch(self.by_uuid_url.replace("{}", &uuid.simple().to_string()))
+            .await
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    use super::{GameProfile, ProfileResolver};
+
+    /// In-process resolver backed by a map, names can be changed to simulate renames
+    #[derive(Default)]
+    pub struct FakeProfileResolver {
+        pub profiles: RwLock<HashMap<Uuid, String>>,
+    }
+
+    impl FakeProfileResolver {
+        pub async fn insert(&self, uuid: Uuid, name: &str) {
+            self.profiles.write().await.insert(uuid, name.to_owned());
+        }
+    }
+
+    #[async_trait]
+    impl ProfileResolver for FakeProfileResolver {
+        async fn by_username(&self, username: &str) -> Result<Option<GameProfile>> {
+            let profiles = self.profiles.read().await;
+            Ok(profiles
+                .iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(username))
+                .map(|(uuid, name)| GameProfile {
+                    uuid: *uuid,
+                    name: name.clone(),
+                }))
+        }
+
+        async fn by_uuid(&self, uuid: Uuid) -> Result<Option<GameProfile>> {
+            let profiles = self.profiles.read().await;
+            Ok(profiles.get(&uuid).map(|name| GameProfile {
+                uuid,
+                name: name.clone(),
+            }))
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use anyhow::{bail, Context, Result};
+use sled::{Batch, Db};
+use tracing::{info, warn};
+use twilight_model::id::{
+    marker::{GuildMarker, RoleMarker},
+    Id,
+};
+
+use crate::models::LFGType;
+
+/// Version of the data layout this build reads and writes
+pub const SCHEMA_VERSION: u64 = 1;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Upgrades a database one version, from the version at its index
+type Migration = fn(&Db, &MigrationOptions) -> Result<()>;
+
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [migrate_guild_mention_types];
+
+#[derive(Debug, Clone, Default)]
+pub struct MigrationOptions {
+    /// the guild mention types from before guilds were tracked belong to, the migration
+    /// refuses to run without one while there are any
+    pub legacy_guild: Option<Id<GuildMarker>>,
+}
+
+pub fn encode_u64(value: u64) -> [u8; 8] {
+    value.to_be_bytes()
+}
+
+pub fn decode_u64(bytes: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = match bytes.try_into() {
+        Ok(v) => v,
+        Err(_) => bail!("expected 8 bytes for a u64, got {:?}", bytes),
+    };
+    Ok(u64::from_be_bytes(bytes))
+}
+
+pub fn id_key<T>(id: Id<T>) -> [u8; 8] {
+    encode_u64(id.get())
+}
+
+pub fn decode_id<T>(bytes: &[u8]) -> Result<Id<T>> {
+    let value = decode_u64(bytes)?;
+    Id::new_checked(value).context("ids can't be zero")
+}
+
+pub fn mention_type_key(guild: Id<GuildMarker>, facade: Id<RoleMarker>) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&id_key(guild));
+    key[8..].copy_from_slice(&id_key(facade));
+    key
+}
+
+pub fn decode_mention_type_key(bytes: &[u8]) -> Result<(Id<GuildMarker>, Id<RoleMarker>)> {
+    if bytes.len() != 16 {
+        bail!("expected 16 bytes for a mention type key, got {:?}", bytes);
+    }
+    Ok((decode_id(&bytes[..8])?, decode_id(&bytes[8..])?))
+}
+
+pub fn schema_version(db: &Db) -> Result<u64> {
+    match db
+        .get(SCHEMA_VERSION_KEY)
+        .context("reading schema version")?
+    {
+        Some(v) => decode_u64(&v).context("decoding schema version"),
+        // nothing stored means a fresh database or one from before versioning
+        None => Ok(0),
+    }
+}
+
+/// Brings the database up to [`SCHEMA_VERSION`], meant to run before anything reads it
+pub fn migrate(db: &Db, options: &MigrationOptions) -> Result<()> {
+    let mut version = schema_version(db)?;
+
+    if version > SCHEMA_VERSION {
+        bail!(
+            "the database is at schema version {version}, newer than the {SCHEMA_VERSION} this build understands"
+        );
+    }
+
+    while version < SCHEMA_VERSION {
+        MIGRATIONS[version as usize](db, options)
+            .with_context(|| format!("migrating from schema version {version}"))?;
+        version += 1;
+
+        db.insert(SCHEMA_VERSION_KEY, &encode_u64(version))
+            .context("storing schema version")?;
+        db.flush().context("flushing migration")?;
+        info!("migrated database to schema version {version}");
+    }
+
+    Ok(())
+}
+
+/// Version 0 stored mention types under their facade for every guild, first as bare facade
+/// to actual role id pairs and later as a full [`LFGType`]. Version 1 stores an [`LFGType`]
+/// per guild and facade
+fn migrate_guild_mention_types(db: &Db, options: &MigrationOptions) -> Result<()> {
+    let mention_types = db.open_tree("mention_types")?;
+    let mut batch = Batch::default();
+    let mut migrated = 0;
+
+    for entry in mention_types.iter() {
+        let (key, value) = entry.context("reading mention types")?;
+
+        // mention types already stored per guild were written by a later build
+        if key.len() == 16 {
+            continue;
+        }
+
+        let facade = decode_id::<RoleMarker>(&key)
+            .with_context(|| format!("decoding legacy facade role {:?}", key))?;
+        let lfg_type = if value.len() == 8 {
+            let actual = decode_id::<RoleMarker>(&value)
+                .with_context(|| format!("decoding legacy actual role for {facade}"))?;
+            LFGType::new(facade, actual)
+        } else {
+            serde_json::from_slice::<LFGType>(&value)
+                .with_context(|| format!("decoding legacy mention type for {facade}"))?
+        };
+
+        // bailing leaves the tree and version as they were, so nothing is lost
+        let guild = match options.legacy_guild {
+            Some(v) => v,
+            None => bail!(
+                "mention type for {facade} was stored before guilds were tracked, set storage.legacy_guild_id to the guild it belongs to"
+            ),
+        };
+
+        batch.remove(key);
+
+        // the type was added again since, which is newer than what we'd migrate
+        let new_key = mention_type_key(guild, facade);
+        if mention_types
+            .contains_key(new_key)
+            .context("reading mention types")?
+        {
+            warn!(%facade, %guild, "dropping legacy mention type, the guild already has one for its facade");
+            continue;
+        }
+
+        let value = serde_json::to_vec(&lfg_type).context("serializing mention type")?;
+        batch.insert(&new_key, value);
+        migrated += 1;
+    }
+
+    // all or nothing, a bad entry leaves the tree as it was
+    mention_types
+        .apply_batch(batch)
+        .context("storing migrated mention types")?;
+
+    info!("moved {migrated} mention types into their guild");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sled::Db;
+    use twilight_model::id::{
+        marker::{GuildMarker, RoleMarker},
+        Id,
+    };
+
+    use super::{
+        decode_id, decode_mention_type_key, decode_u64, encode_u64, mention_type_key, migrate,
+        schema_version, MigrationOptions, SCHEMA_VERSION,
+    };
+    use crate::models::LFGType;
+
+    fn database() -> Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    /// A database as the first release left it
+    fn version_0() -> Db {
+        let db = database();
+        let mention_types = db.open_tree("mention_types").unwrap();
+        for (facade, actual) in [(11u64, 12u64), (21, 22)] {
+            mention_types
+                .insert(facade.to_be_bytes(), &actual.to_be_bytes())
+                .unwrap();
+        }
+        db
+    }
+
+    fn stored_types(db: &Db) -> Vec<(Id<GuildMarker>, LFGType)> {
+        db.open_tree("mention_types")
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.unwrap();
+                let (guild, facade) = decode_mention_type_key(&key).unwrap();
+                let lfg_type: LFGType = serde_json::from_slice(&value).unwrap();
+                assert_eq!(lfg_type.facade, facade);
+                (guild, lfg_type)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn helpers_refuse_malformed_bytes() {
+        assert_eq!(decode_u64(&encode_u64(42)).unwrap(), 42);
+        assert!(decode_u64(&[1, 2, 3]).is_err());
+        assert!(decode_u64(&[0; 9]).is_err());
+        assert!(decode_id::<RoleMarker>(&[0; 8]).is_err());
+
+        let key = mention_type_key(Id::new(1), Id::new(2));
+        assert_eq!(
+            decode_mention_type_key(&key).unwrap(),
+            (Id::new(1), Id::new(2))
+        );
+        assert!(decode_mention_type_key(&key[..8]).is_err());
+    }
+
+    #[test]
+    fn migrates_version_0_into_legacy_guild() {
+        let db = version_0();
+        let options = MigrationOptions {
+            legacy_guild: Some(Id::new(5)),
+        };
+
+        migrate(&db, &options).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+
+        let stored = stored_types(&db);
+        assert_eq!(stored.len(), 2);
+        for (guild, lfg_type) in &stored {
+            assert_eq!(*guild, Id::new(5));
+            assert_eq!(lfg_type.actual.get(), lfg_type.facade.get() + 1);
+        }
+
+        // running again changes nothing
+        migrate(&db, &options).unwrap();
+        assert_eq!(stored_types(&db).len(), 2);
+    }
+
+    #[test]
+    fn migrates_version_0_without_legacy_guild() {
+        let db = version_0();
+
+        // a later build already stored one per guild before versioning existed
+        let lfg_type = LFGType::new(Id::new(31), Id::new(32));
+        db.open_tree("mention_types")
+            .unwrap()
+            .insert(
+                mention_type_key(Id::new(6), lfg_type.facade),
+                serde_json::to_vec(&lfg_type).unwrap(),
+            )
+            .unwrap();
+
+        assert!(migrate(&db, &MigrationOptions::default()).is_err());
+        assert_eq!(schema_version(&db).unwrap(), 0);
+
+        // every legacy entry is still there for a run with the guild set
+        let mention_types = db.open_tree("mention_types").unwrap();
+        assert_eq!(mention_types.len(), 3);
+        assert!(mention_types.contains_key(11u64.to_be_bytes()).unwrap());
+        assert!(mention_types.contains_key(21u64.to_be_bytes()).unwrap());
+
+        let options = MigrationOptions {
+            legacy_guild: Some(Id::new(5)),
+        };
+        migrate(&db, &options).unwrap();
+        assert_eq!(stored_types(&db).len(), 3);
+    }
+
+    #[test]
+    fn migrates_full_records_and_keeps_readded_types() {
+        let db = version_0();
+        let mention_types = db.open_tree("mention_types").unwrap();
+
+        // later version 0 builds stored the whole record under the bare facade
+        let mut record = LFGType::new(Id::new(31), Id::new(32));
+        record.team_size = Some(4);
+        mention_types
+            .insert(31u64.to_be_bytes(), serde_json::to_vec(&record).unwrap())
+            .unwrap();
+
+        // and the admin re-added one of the bare pairs under the guild since
+        let readded = LFGType::new(Id::new(11), Id::new(99));
+        mention_types
+            .insert(
+                mention_type_key(Id::new(5), readded.facade),
+                serde_json::to_vec(&readded).unwrap(),
+            )
+            .unwrap();
+
+        let options = MigrationOptions {
+            legacy_guild: Some(Id::new(5)),
+        };
+        migrate(&db, &options).unwrap();
+
+        let stored = stored_types(&db);
+        assert_eq!(stored.len(), 3);
+        assert!(stored.iter().all(|(guild, _)| *guild == Id::new(5)));
+
+        let actual_of = |facade: u64| {
+            stored
+                .iter()
+                .find(|(_, it)| it.facade == Id::new(facade))
+                .map(|(_, it)| (it.actual, it.team_size))
+                .unwrap()
+        };
+        assert_eq!(actual_of(11), (Id::new(99), None));
+        assert_eq!(actual_of(21), (Id::new(22), None));
+        assert_eq!(actual_of(31), (Id::new(32), Some(4)));
+    }
+
+    #[test]
+    fn malformed_version_0_is_left_alone() {
+        let db = version_0();
+        db.open_tree("mention_types")
+            .unwrap()
+            .insert(31u64.to_be_bytes(), &[1, 2, 3])
+            .unwrap();
+
+        let options = MigrationOptions {
+            legacy_guild: Some(Id::new(5)),
+        };
+        assert!(migrate(&db, &options).is_err());
+        assert_eq!(schema_version(&db).unwrap(), 0);
+        assert_eq!(db.open_tree("mention_types").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn fresh_and_newer_databases() {
+        let db = database();
+        migrate(&db, &MigrationOptions::default()).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+
+        db.insert(b"schema_version", &encode_u64(SCHEMA_VERSION + 1))
+            .unwrap();
+        assert!(migrate(&db, &MigrationOptions::default()).is_err());
+    }
+}
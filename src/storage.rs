@@ -0,0 +1,513 @@
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use sled::{Db, Tree};
+use tracing::warn;
+use twilight_model::id::{
+    marker::{GuildMarker, RoleMarker, UserMarker},
+    Id,
+};
+use uuid::Uuid;
+
+use crate::{
+    models::{ChairmanLink, ChairmanUser, GuildSettings, LFGSession, LFGType},
+    schema,
+};
+
+/// Everything the bot keeps between restarts
+pub trait Storage: Send + Sync {
+    fn mention_type(
+        &self,
+        guild: Id<GuildMarker>,
+        facade: Id<RoleMarker>,
+    ) -> Result<Option<LFGType>>;
+    fn mention_types(&self, guild: Id<GuildMarker>) -> Result<Vec<LFGType>>;
+    fn store_mention_type(&self, guild: Id<GuildMarker>, lfg_type: &LFGType) -> Result<()>;
+    /// Returns whether there was a mention type to remove
+    fn remove_mention_type(&self, guild: Id<GuildMarker>, facade: Id<RoleMarker>) -> Result<bool>;
+    /// Returns how many mention types were removed
+    fn remove_guild_mention_types(&self, guild: Id<GuildMarker>) -> Result<usize>;
+
+    fn guild_settings(&self, guild: Id<GuildMarker>) -> Result<Option<GuildSettings>>;
+    fn store_guild_settings(&self, guild: Id<GuildMarker>, settings: &GuildSettings) -> Result<()>;
+    fn remove_guild_settings(&self, guild: Id<GuildMarker>) -> Result<()>;
+
+    /// Every stored session, unreadable ones are dropped
+    fn sessions(&self) -> Result<Vec<LFGSession>>;
+    fn store_session(&self, session: &LFGSession) -> Result<()>;
+    fn remove_session(&self, uuid: Uuid) -> Result<()>;
+
+    fn user(&self, id: Id<UserMarker>) -> Result<Option<ChairmanUser>>;
+    fn store_user(&self, user: &ChairmanUser) -> Result<()>;
+
+    fn link(&self, uuid: Uuid) -> Result<Option<ChairmanLink>>;
+    fn links(&self) -> Result<Vec<ChairmanLink>>;
+    fn store_link(&self, link: &ChairmanLink) -> Result<()>;
+    /// Stores the link only if the account isn't linked yet, returning whether it was stored
+    fn claim_link(&self, link: &ChairmanLink) -> Result<bool>;
+    fn remove_link(&self, uuid: Uuid) -> Result<()>;
+
+    /// Makes sure everything written so far survives a restart
+    fn flush(&self) -> Result<()>;
+}
+
+/// Storage in sled trees, values are JSON
+pub struct SledStorage {
+    db: Db,
+    mention_types: Tree,
+    guild_settings: Tree,
+    sessions: Tree,
+    users: Tree,
+    links: Tree,
+}
+
+fn encode<T: Serialize>(value: &T, what: &str) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).with_context(|| format!("serializing {what}"))
+}
+
+fn decode<T: DeserializeOwned>(value: &[u8], what: &str) -> Result<T> {
+    serde_json::from_slice(value).with_context(|| format!("deserializing {what}"))
+}
+
+/// Mention types that can't be read are skipped rather than breaking every ping
+fn decode_mention_type(value: &[u8]) -> Option<LFGType> {
+    match serde_json::from_slice(value) {
+        Ok(v) => Some(v),
+        Err(cause) => {
+            warn!(?cause, "unreadable mention type");
+            None
+        }
+    }
+}
+
+impl SledStorage {
+    /// Opens the trees, the database should already be migrated
+    pub fn new(db: Db) -> Result<Self> {
+        Ok(SledStorage {
+            mention_types: db.open_tree("mention_types")?,
+            guild_settings: db.open_tree("guild_settings")?,
+            sessions: db.open_tree("sessions")?,
+            users: db.open_tree("users")?,
+            links: db.open_tree("links")?,
+            db,
+        })
+    }
+}
+
+impl Storage for SledStorage {
+    fn mention_type(
+        &self,
+        guild: Id<GuildMarker>,
+        facade: Id<RoleMarker>,
+    ) -> Result<Option<LFGType>> {
+        let value = self
+            .mention_types
+            .get(schema::mention_type_key(guild, facade))
+            .context("reading mention type")?;
+        Ok(value.and_then(|it| decode_mention_type(&it)))
+    }
+
+    fn mention_types(&self, guild: Id<GuildMarker>) -> Result<Vec<LFGType>> {
+        let mut lfg_types = Vec::new();
+        for entry in self.mention_types.scan_prefix(schema::id_key(guild)) {
+            let (key, value) = entry.context("reading mention types")?;
+
+            if let Err(cause) = schema::decode_mention_type_key(&key) {
+                warn!(?cause, "skipping mention type with a malformed key");
+                continue;
+            }
+
+            if let Some(lfg_type) = decode_mention_type(&value) {
+                lfg_types.push(lfg_type);
+            }
+        }
+
+        Ok(lfg_types)
+    }
+
+    fn store_mention_type(&self, guild: Id<GuildMarker>, lfg_type: &LFGType) -> Result<()> {
+        self.mention_types
+            .insert(
+                schema::mention_type_key(guild, lfg_type.facade),
+                encode(lfg_type, "mention type")?,
+            )
+            .context("storing mention type")?;
+        Ok(())
+    }
+
+    fn remove_mention_type(&self, guild: Id<GuildMarker>, facade: Id<RoleMarker>) -> Result<bool> {
+        Ok(self
+            .mention_types
+            .remove(schema::mention_type_key(guild, facade))
+            .context("removing mention type")?
+            .is_some())
+    }
+
+    fn remove_guild_mention_types(&self, guild: Id<GuildMarker>) -> Result<usize> {
+        let mut removed = 0;
+        for key in self.mention_types.scan_prefix(schema::id_key(guild)).keys() {
+            let key = key.context("reading mention types")?;
+            self.mention_types
+                .remove(key)
+                .context("removing mention type")?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn guild_settings(&self, guild: Id<GuildMarker>) -> Result<Option<GuildSettings>> {
+        match self
+            .guild_settings
+            .get(schema::id_key(guild))
+            .context("reading guild settings")?
+        {
+            Some(v) => Ok(Some(decode(&v, "guild settings")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn store_guild_settings(&self, guild: Id<GuildMarker>, settings: &GuildSettings) -> Result<()> {
+        self.guild_settings
+            .insert(schema::id_key(guild), encode(settings, "guild settings")?)
+            .context("storing guild settings")?;
+        Ok(())
+    }
+
+    fn remove_guild_settings(&self, guild: Id<GuildMarker>) -> Result<()> {
+        self.guild_settings
+            .remove(schema::id_key(guild))
+            .context("removing guild settings")?;
+        Ok(())
+    }
+
+    fn sessions(&self) -> Result<Vec<LFGSession>> {
+        let mut sessions = Vec::new();
+        for entry in self.sessions.iter() {
+            let (key, value) = match entry {
+                Ok(v) => v,
+                Err(cause) => {
+                    warn!(?cause, "error reading stored sessions");
+                    continue;
+                }
+            };
+
+            match serde_json::from_slice::<LFGSession>(&value) {
+                Ok(v) => sessions.push(v),
+                Err(cause) => {
+                    warn!(?cause, ?key, "dropping unreadable stored session");
+                    self.sessions
+                        .remove(key)
+                        .context("removing stored session")?;
+                }
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    fn store_session(&self, session: &LFGSession) -> Result<()> {
+        self.sessions
+            .insert(session.uuid.as_bytes(), encode(session, "session")?)
+            .context("storing session")?;
+        Ok(())
+    }
+
+    fn remove_session(&self, uuid: Uuid) -> Result<()> {
+        self.sessions
+            .remove(uuid.as_bytes())
+            .context("removing stored session")?;
+        Ok(())
+    }
+
+    fn user(&self, id: Id<UserMarker>) -> Result<Option<ChairmanUser>> {
+        match self.users.get(schema::id_key(id)).context("reading user")? {
+            Some(v) => Ok(Some(decode(&v, &format!("user {id}"))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn store_user(&self, user: &ChairmanUser) -> Result<()> {
+        self.users
+            .insert(schema::id_key(user.id), encode(user, "user")?)
+            .context("storing user")?;
+        Ok(())
+    }
+
+    fn link(&self, uuid: Uuid) -> Result<Option<ChairmanLink>> {
+        match self.links.get(uuid.as_bytes()).context("reading link")? {
+            Some(v) => Ok(Some(decode(&v, &format!("link {uuid}"))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn links(&self) -> Result<Vec<ChairmanLink>> {
+        let mut links = Vec::new();
+        for entry in self.links.iter() {
+            let (_, value) = entry.context("reading links")?;
+            links.push(decode(&value, "link")?);
+        }
+        Ok(links)
+    }
+
+    fn store_link(&self, link: &ChairmanLink) -> Result<()> {
+        self.links
+            .insert(link.uuid.as_bytes(), encode(link, "link")?)
+            .context("storing link")?;
+        Ok(())
+    }
+
+    fn claim_link(&self, link: &ChairmanLink) -> Result<bool> {
+        let claimed = self
+            .links
+            .compare_and_swap(
+                link.uuid.as_bytes(),
+                None as Option<&[u8]>,
+                Some(encode(link, "link")?),
+            )
+            .context("storing link")?;
+        Ok(claimed.is_ok())
+    }
+
+    fn remove_link(&self, uuid: Uuid) -> Result<()> {
+        self.links
+            .remove(uuid.as_bytes())
+            .context("removing link")?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush().context("flushing database")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::Mutex,
+    };
+
+    use anyhow::Result;
+    use twilight_model::id::{
+        marker::{GuildMarker, RoleMarker, UserMarker},
+        Id,
+    };
+    use uuid::Uuid;
+
+    use super::Storage;
+    use crate::models::{ChairmanLink, ChairmanUser, GuildSettings, LFGSession, LFGType};
+
+    #[derive(Default)]
+    struct Data {
+        mention_types: BTreeMap<(Id<GuildMarker>, Id<RoleMarker>), LFGType>,
+        guild_settings: HashMap<Id<GuildMarker>, GuildSettings>,
+        sessions: HashMap<Uuid, LFGSession>,
+        users: HashMap<Id<UserMarker>, ChairmanUser>,
+        links: HashMap<Uuid, ChairmanLink>,
+    }
+
+    /// Storage that lives and dies with the process
+    #[derive(Default)]
+    pub struct MemoryStorage {
+        data: Mutex<Data>,
+    }
+
+    impl MemoryStorage {
+        fn data(&self) -> std::sync::MutexGuard<'_, Data> {
+            self.data.lock().expect("storage lock poisoned")
+        }
+    }
+
+    impl Storage for MemoryStorage {
+        fn mention_type(
+            &self,
+            guild: Id<GuildMarker>,
+            facade: Id<RoleMarker>,
+        ) -> Result<Option<LFGType>> {
+            Ok(self.data().mention_types.get(&(guild, facade)).cloned())
+        }
+
+        fn mention_types(&self, guild: Id<GuildMarker>) -> Result<Vec<LFGType>> {
+            Ok(self
+                .data()
+                .mention_types
+                .iter()
+                .filter(|((it, _), _)| *it == guild)
+                .map(|(_, lfg_type)| lfg_type.clone())
+                .collect())
+        }
+
+        fn store_mention_type(&self, guild: Id<GuildMarker>, lfg_type: &LFGType) -> Result<()> {
+            self.data()
+                .mention_types
+                .insert((guild, lfg_type.facade), lfg_type.clone());
+            Ok(())
+        }
+
+        fn remove_mention_type(
+            &self,
+            guild: Id<GuildMarker>,
+            facade: Id<RoleMarker>,
+        ) -> Result<bool> {
+            Ok(self.data().mention_types.remove(&(guild, facade)).is_some())
+        }
+
+        fn remove_guild_mention_types(&self, guild: Id<GuildMarker>) -> Result<usize> {
+            let mut data = self.data();
+            let before = data.mention_types.len();
+            data.mention_types.retain(|(it, _), _| *it != guild);
+            Ok(before - data.mention_types.len())
+        }
+
+        fn guild_settings(&self, guild: Id<GuildMarker>) -> Result<Option<GuildSettings>> {
+            Ok(self.data().guild_settings.get(&guild).cloned())
+        }
+
+        fn store_guild_settings(
+            &self,
+            guild: Id<GuildMarker>,
+            settings: &GuildSettings,
+        ) -> Result<()> {
+            self.data().guild_settings.insert(guild, settings.clone());
+            Ok(())
+        }
+
+        fn remove_guild_settings(&self, guild: Id<GuildMarker>) -> Result<()> {
+            self.data().guild_settings.remove(&guild);
+            Ok(())
+        }
+
+        fn sessions(&self) -> Result<Vec<LFGSession>> {
+            Ok(self.data().sessions.values().cloned().collect())
+        }
+
+        fn store_session(&self, session: &LFGSession) -> Result<()> {
+            self.data().sessions.insert(session.uuid, session.clone());
+            Ok(())
+        }
+
+        fn remove_session(&self, uuid: Uuid) -> Result<()> {
+            self.data().sessions.remove(&uuid);
+            Ok(())
+        }
+
+        fn user(&self, id: Id<UserMarker>) -> Result<Option<ChairmanUser>> {
+            Ok(self.data().users.get(&id).cloned())
+        }
+
+        fn store_user(&self, user: &ChairmanUser) -> Result<()> {
+            self.data().users.insert(user.id, user.clone());
+            Ok(())
+        }
+
+        fn link(&self, uuid: Uuid) -> Result<Option<ChairmanLink>> {
+            Ok(self.data().links.get(&uuid).cloned())
+        }
+
+        fn links(&self) -> Result<Vec<ChairmanLink>> {
+            Ok(self.data().links.values().cloned().collect())
+        }
+
+        fn store_link(&self, link: &ChairmanLink) -> Result<()> {
+            self.data().links.insert(link.uuid, link.clone());
+            Ok(())
+        }
+
+        fn claim_link(&self, link: &ChairmanLink) -> Result<bool> {
+            let mut data = self.data();
+            if data.links.contains_key(&link.uuid) {
+                return Ok(false);
+            }
+            data.links.insert(link.uuid, link.clone());
+            Ok(true)
+        }
+
+        fn remove_link(&self, uuid: Uuid) -> Result<()> {
+            self.data().links.remove(&uuid);
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use twilight_model::id::Id;
+    use uuid::Uuid;
+
+    use super::{memory::MemoryStorage, SledStorage, Storage};
+    use crate::models::{ChairmanLink, GuildSettings, LFGType, MultiRoleMode};
+
+    fn backends() -> Vec<Box<dyn Storage>> {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        vec![
+            Box::new(SledStorage::new(db).unwrap()),
+            Box::new(MemoryStorage::default()),
+        ]
+    }
+
+    #[test]
+    fn mention_types_stay_in_their_guild() {
+        for storage in backends() {
+            let first = LFGType::new(Id::new(11), Id::new(12));
+            let second = LFGType::new(Id::new(21), Id::new(22));
+            storage.store_mention_type(Id::new(1), &first).unwrap();
+            storage.store_mention_type(Id::new(1), &second).unwrap();
+            storage.store_mention_type(Id::new(2), &first).unwrap();
+
+            assert_eq!(storage.mention_types(Id::new(1)).unwrap().len(), 2);
+            assert!(storage
+                .mention_type(Id::new(2), second.facade)
+                .unwrap()
+                .is_none());
+
+            assert!(storage
+                .remove_mention_type(Id::new(2), first.facade)
+                .unwrap());
+            assert!(!storage
+                .remove_mention_type(Id::new(2), first.facade)
+                .unwrap());
+            assert_eq!(storage.remove_guild_mention_types(Id::new(1)).unwrap(), 2);
+            assert!(storage.mention_types(Id::new(1)).unwrap().is_empty());
+
+            let settings = GuildSettings {
+                multi_role_mode: MultiRoleMode::Separate,
+            };
+            storage.store_guild_settings(Id::new(1), &settings).unwrap();
+            assert!(storage.guild_settings(Id::new(1)).unwrap().is_some());
+            storage.remove_guild_settings(Id::new(1)).unwrap();
+            assert!(storage.guild_settings(Id::new(1)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn links_are_claimed_once() {
+        for storage in backends() {
+            let now = Utc::now();
+            let link = ChairmanLink {
+                uuid: Uuid::new_v4(),
+                parent: Id::new(1),
+                created: now,
+                updated: now,
+                last_username: "wife".to_owned(),
+            };
+
+            assert!(storage.claim_link(&link).unwrap());
+            assert!(!storage
+                .claim_link(&ChairmanLink {
+                    parent: Id::new(2),
+                    ..link.clone()
+                })
+                .unwrap());
+            assert_eq!(storage.link(link.uuid).unwrap().unwrap().parent, Id::new(1));
+            assert_eq!(storage.links().unwrap().len(), 1);
+
+            storage.remove_link(link.uuid).unwrap();
+            assert!(storage.link(link.uuid).unwrap().is_none());
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use tokio::time;
+use tracing::{info, warn};
+use twilight_model::id::{marker::UserMarker, Id};
+use uuid::Uuid;
+
+use crate::{
+    models::{ChairmanLink, ChairmanUser},
+    profile::ProfileResolver,
+    storage::Storage,
+};
+
+pub struct UserManager {
+    storage: Arc<dyn Storage>,
+}
+
+pub enum LinkOutcome {
+    Linked,
+    /// the account was already linked to the same user, its username got refreshed
+    Updated,
+    OwnedByOther,
+}
+
+impl UserManager {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        UserManager { storage }
+    }
+
+    pub fn user(&self, id: Id<UserMarker>) -> Result<Option<ChairmanUser>> {
+        self.storage.user(id)
+    }
+
+    pub fn store_user(&self, user: &ChairmanUser) -> Result<()> {
+        self.storage.store_user(user)
+    }
+
+    pub fn is_administrator(&self, id: Id<UserMarker>) -> Result<bool> {
+        Ok(self.user(id)?.map(|it| it.administrator).unwrap_or(false))
+    }
+
+    pub fn set_administrator(&self, id: Id<UserMarker>, administrator: bool) -> Result<()> {
+        let mut user = self.user(id)?.unwrap_or_else(|| ChairmanUser::new(id));
+        user.administrator = administrator;
+        user.updated = Utc::now();
+        self.store_user(&user)
+    }
+
+    pub fn link(&self, uuid: Uuid) -> Result<Option<ChairmanLink>> {
+        self.storage.link(uuid)
+    }
+
+    pub fn store_link(&self, link: &ChairmanLink) -> Result<()> {
+        self.storage.store_link(link)
+    }
+
+    /// Every account linked to a user, main account first
+    pub fn linked_accounts(&self, id: Id<UserMarker>) -> Result<Vec<ChairmanLink>> {
+        let user = match self.user(id)? {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut links = Vec::new();
+        for uuid in user.linked_uuids.iter().flatten() {
+            if let Some(link) = self.link(*uuid)? {
+                links.push(link);
+            }
+        }
+
+        links.sort_by_key(|it| Some(it.uuid) != user.main_link);
+        Ok(links)
+    }
+
+    /// The last known username of the user's main account, if they have one
+    pub fn main_username(&self, id: Id<UserMarker>) -> Result<Option<String>> {
+        let main_link = match self.user(id)?.and_then(|it| it.main_link) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        Ok(self.link(main_link)?.map(|it| it.last_username))
+    }
+
+    pub fn link_account(
+        &self,
+        id: Id<UserMarker>,
+        uuid: Uuid,
+        username: &str,
+    ) -> Result<LinkOutcome> {
+        let now = Utc::now();
+
+        if let Some(mut link) = self.link(uuid)? {
+            if link.parent != id {
+                return Ok(LinkOutcome::OwnedByOther);
+            }
+
+            link.last_username = username.to_owned();
+            link.updated = now;
+            self.store_link(&link)?;
+            return Ok(LinkOutcome::Updated);
+        }
+
+        let link = ChairmanLink {
+            uuid,
+            parent: id,
+            created: now,
+            updated: now,
+            last_username: username.to_owned(),
+        };
+
+        // only claim the account if nobody else got to it in the meantime
+        if !self.storage.claim_link(&link)? {
+            return match self.link(uuid)? {
+                Some(existing) if existing.parent != id => Ok(LinkOutcome::OwnedByOther),
+                _ => Ok(LinkOutcome::Updated),
+            };
+        }
+
+        let mut user = self.user(id)?.unwrap_or_else(|| ChairmanUser::new(id));
+        user.linked_uuids.get_or_insert_with(Vec::new).push(uuid);
+        user.main_link.get_or_insert(uuid);
+        user.updated = now;
+        self.store_user(&user)?;
+
+        Ok(LinkOutcome::Linked)
+    }
+
+    /// Returns whether the account is linked to the user
+    pub fn set_main_link(&self, id: Id<UserMarker>, uuid: Uuid) -> Result<bool> {
+        let mut user = match self.user(id)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+
+        if !user.linked_uuids.iter().flatten().any(|it| *it == uuid) {
+            return Ok(false);
+        }
+
+        user.main_link = Some(uuid);
+        user.updated = Utc::now();
+        self.store_user(&user)?;
+        Ok(true)
+    }
+
+    /// Returns whether the account was linked to the user
+    pub fn unlink_account(&self, id: Id<UserMarker>, uuid: Uuid) -> Result<bool> {
+        match self.link(uuid)? {
+            Some(link) if link.parent == id => {}
+            _ => return Ok(false),
+        }
+
+        self.storage.remove_link(uuid)?;
+
+        if let Some(mut user) = self.user(id)? {
+            let linked = user.linked_uuids.get_or_insert_with(Vec::new);
+            linked.retain(|it| *it != uuid);
+            if user.main_link == Some(uuid) {
+                user.main_link = linked.first().copied();
+            }
+            user.updated = Utc::now();
+            self.store_user(&user)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Re-resolves the username of every link that hasn't been updated within `max_age`,
+    /// returning how many links were refreshed
+    pub async fn refresh_stale_links(
+        &self,
+        resolver: &dyn ProfileResolver,
+        max_age: Duration,
+    ) -> Result<usize> {
+        let cutoff = Utc::now() - max_age;
+
+        let stale = self
+            .storage
+            .links()?
+            .into_iter()
+            .filter(|it| it.updated < cutoff)
+            .collect::<Vec<_>>();
+
+        let mut refreshed = 0;
+        for mut link in stale {
+            let profile = match resolver.by_uuid(link.uuid).await {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    warn!(uuid = %link.uuid, "linked account no longer resolves");
+                    continue;
+                }
+                Err(cause) => {
+                    warn!(?cause, uuid = %link.uuid, "error resolving linked account");
+                    continue;
+                }
+            };
+
+            // the link may have been removed while we were resolving
+            if self.link(link.uuid)?.is_none() {
+                continue;
+            }
+
+            link.last_username = profile.name;
+            link.updated = Utc::now();
+            self.store_link(&link)?;
+            refreshed += 1;
+        }
+
+        Ok(refreshed)
+    }
+
+    /// Periodically refreshes stale links, meant to be spawned once
+    pub async fn run_link_refresh(&self, resolver: Arc<dyn ProfileResolver>) {
+        let mut interval = time::interval(time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+
+            match self
+                .refresh_stale_links(resolver.as_ref(), Duration::days(1))
+                .await
+            {
+                Ok(0) => {}
+                Ok(refreshed) => info!("refreshed {} linked accounts", refreshed),
+                Err(cause) => warn!(?cause, "error refreshing linked accounts"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use twilight_model::id::Id;
+    use uuid::Uuid;
+
+    use crate::{profile::fake::FakeProfileResolver, storage::memory::MemoryStorage};
+
+    use super::{LinkOutcome, UserManager};
+
+    fn manager() -> UserManager {
+        UserManager::new(Arc::new(MemoryStorage::default()))
+    }
+
+    #[test]
+    fn account_links_to_one_user() {
+        let users = manager();
+        let uuid = Uuid::new_v4();
+
+        assert!(matches!(
+            users.link_account(Id::new(1), uuid, "wife").unwrap(),
+            LinkOutcome::Linked
+        ));
+        assert!(matches!(
+            users.link_account(Id::new(2), uuid, "wife").unwrap(),
+            LinkOutcome::OwnedByOther
+        ));
+        assert!(matches!(
+            users.link_account(Id::new(1), uuid, "Wife").unwrap(),
+            LinkOutcome::Updated
+        ));
+
+        let user = users.user(Id::new(1)).unwrap().unwrap();
+        assert_eq!(user.main_link, Some(uuid));
+        assert_eq!(users.link(uuid).unwrap().unwrap().last_username, "Wife");
+        assert!(users.user(Id::new(2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn unlinking_main_promotes_next_link() {
+        let users = manager();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        users.link_account(Id::new(1), first, "first").unwrap();
+        users.link_account(Id::new(1), second, "second").unwrap();
+
+        assert!(!users.unlink_account(Id::new(2), first).unwrap());
+        assert!(users.unlink_account(Id::new(1), first).unwrap());
+
+        let user = users.user(Id::new(1)).unwrap().unwrap();
+        assert_eq!(user.main_link, Some(second));
+        assert_eq!(user.linked_uuids, Some(vec![second]));
+        assert!(users.link(first).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn refresh_renames_only_stale_links() {
+        let users = manager();
+        let resolver = FakeProfileResolver::default();
+        let (stale, fresh) = (Uuid::new_v4(), Uuid::new_v4());
+
+        users.link_account(Id::new(1), stale, "old").unwrap();
+        users.link_account(Id::new(1), fresh, "fresh").unwrap();
+        resolver.insert(stale, "renamed").await;
+        resolver.insert(fresh, "also renamed").await;
+
+        let mut link = users.link(stale).unwrap().unwrap();
+        link.updated = Utc::now() - Duration::days(2);
+        users.store_link(&link).unwrap();
+
+        let refreshed = users
+            .refresh_stale_links(&resolver, Duration::days(1))
+            .await
+            .unwrap();
+
+        assert_eq!(refreshed, 1);
+        assert_eq!(users.link(stale).unwrap().unwrap().last_username, "renamed");
+        assert_eq!(users.link(fresh).unwrap().unwrap().last_username, "fresh");
+    }
+}
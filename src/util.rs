@@ -1,7 +1,9 @@
 use anyhow::{Context, Result};
-use tracing::warn;
-use twilight_model::channel::message::Embed;
-use twilight_util::builder::embed::EmbedBuilder;
+use twilight_model::{
+    channel::message::{Embed, MessageFlags},
+    http::interaction::{InteractionResponse, InteractionResponseType},
+};
+use twilight_util::builder::{embed::EmbedBuilder, InteractionResponseDataBuilder};
 
 pub fn simple_embed(color: u32, title: &str, desc: &str) -> Result<Embed> {
     Ok(EmbedBuilder::new()
@@ -18,14 +20,14 @@ pub fn simple_embed(color: u32, title: &str, desc: &str) -> Result<Embed> {
         .build())
 }
 
-// really REALLY dirty method
-pub fn coerce_into_u64(slice: &[u8]) -> u64 {
-    if slice.len() < 8 {
-        warn!("incomplete &[u8] trying to be coerced into u64 {:?}", slice);
-        return 0;
+pub fn embed_response(embed: Embed, ephemeral: bool) -> InteractionResponse {
+    let mut data = InteractionResponseDataBuilder::new().embeds([embed]);
+    if ephemeral {
+        data = data.flags(MessageFlags::EPHEMERAL);
     }
 
-    let mut buf = [0u8; 8];
-    buf[..8].copy_from_slice(&slice[..8]);
-    u64::from_be_bytes(buf)
+    InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(data.build()),
+    }
 }